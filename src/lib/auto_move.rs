@@ -0,0 +1,319 @@
+use crate::identification::Identification;
+use crate::map::TileType;
+use crate::player::perform_command;
+use crate::replay::Command;
+use crate::{
+    CombatStats, GameLog, Item, Map, Monster, Name, RunState, Viewshed, MAPHEIGHT, MAPWIDTH,
+};
+use rltk::{DijkstraMap, Point, Rltk};
+use specs::prelude::*;
+
+const EXPLORE_DEPTH: f32 = 1000.0;
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum AutoMoveMode {
+    Explore,
+    Travel { destination: Point },
+    Run { dx: i32, dy: i32 },
+}
+
+/// Tracks a multi-turn movement the player has asked for, one step of which
+/// is taken each time the game waits for input.
+#[derive(Default)]
+pub struct AutoMove {
+    pub mode: Option<AutoMoveMode>,
+    pub last_hp: i32,
+    pub steps: i32,
+    pub open_neighbours: usize,
+    pub seen_items: Vec<Entity>,
+}
+
+pub fn start(ecs: &mut World, mode: AutoMoveMode) {
+    if let Some(reason) = monster_in_view(ecs) {
+        ecs.fetch_mut::<GameLog>().entries.push(reason);
+        return;
+    }
+
+    let hp = player_hp(ecs);
+    let seen_items = items_in_view(ecs);
+    let open = open_neighbours(&ecs.fetch::<Map>(), *ecs.fetch::<Point>());
+    let mut auto_move = ecs.fetch_mut::<AutoMove>();
+    auto_move.mode = Some(mode);
+    auto_move.last_hp = hp;
+    auto_move.steps = 0;
+    auto_move.open_neighbours = open;
+    auto_move.seen_items = seen_items;
+}
+
+pub fn stop(ecs: &mut World, reason: Option<String>) {
+    ecs.fetch_mut::<AutoMove>().mode = None;
+    if let Some(reason) = reason {
+        ecs.fetch_mut::<GameLog>().entries.push(reason);
+    }
+}
+
+/// Takes the next automatic step if one is underway, returning `None` when
+/// the player is in control. Any key press cancels the movement.
+pub fn auto_move_input(ecs: &mut World, ctx: &Rltk) -> Option<RunState> {
+    let mode = ecs.fetch::<AutoMove>().mode?;
+
+    if ctx.key.is_some() {
+        stop(ecs, Some("You stop.".to_string()));
+        return Some(RunState::AwaitingInput);
+    }
+
+    if let Some(reason) = interruption(ecs, mode) {
+        stop(ecs, Some(reason));
+        return Some(RunState::AwaitingInput);
+    }
+
+    let command = match mode {
+        AutoMoveMode::Explore => explore_step(ecs),
+        AutoMoveMode::Travel { destination } => travel_step(ecs, destination),
+        AutoMoveMode::Run { dx, dy } => run_step(ecs, dx, dy),
+    };
+
+    match command {
+        Ok(command) => {
+            {
+                let hp = player_hp(ecs);
+                let mut auto_move = ecs.fetch_mut::<AutoMove>();
+                auto_move.last_hp = hp;
+                auto_move.steps += 1;
+            }
+            Some(perform_command(ecs, command))
+        }
+        Err(reason) => {
+            stop(ecs, reason);
+            Some(RunState::AwaitingInput)
+        }
+    }
+}
+
+fn interruption(ecs: &World, mode: AutoMoveMode) -> Option<String> {
+    let auto_move = ecs.fetch::<AutoMove>();
+
+    if player_hp(ecs) < auto_move.last_hp {
+        return Some("You are hurt!".to_string());
+    }
+
+    if let Some(reason) = monster_in_view(ecs) {
+        return Some(reason);
+    }
+
+    if let AutoMoveMode::Run { .. } = mode {
+        let names = ecs.read_storage::<Name>();
+        for item in items_in_view(ecs) {
+            if !auto_move.seen_items.contains(&item) {
+                if let Some(name) = names.get(item) {
+                    let identification = ecs.fetch::<Identification>();
+                    return Some(format!(
+                        "You see a {}.",
+                        identification.display_name(&name.name)
+                    ));
+                }
+            }
+        }
+    }
+
+    let stops_on_items = !matches!(mode, AutoMoveMode::Travel { .. });
+    if stops_on_items && auto_move.steps > 0 {
+        let map = ecs.fetch::<Map>();
+        let player_pos = ecs.fetch::<Point>();
+        let items = ecs.read_storage::<Item>();
+        let names = ecs.read_storage::<Name>();
+        let idx = map.xy_idx(player_pos.x, player_pos.y);
+        for entity in map.tile_content[idx].iter() {
+            if items.get(*entity).is_some() {
+                if let Some(name) = names.get(*entity) {
+                    let identification = ecs.fetch::<Identification>();
+                    return Some(format!(
+                        "You see a {} here.",
+                        identification.display_name(&name.name)
+                    ));
+                }
+            }
+        }
+    }
+
+    None
+}
+
+fn monster_in_view(ecs: &World) -> Option<String> {
+    let player_entity = ecs.fetch::<Entity>();
+    let viewsheds = ecs.read_storage::<Viewshed>();
+    let monsters = ecs.read_storage::<Monster>();
+    let names = ecs.read_storage::<Name>();
+    let map = ecs.fetch::<Map>();
+
+    let viewshed = viewsheds.get(*player_entity)?;
+    for tile in viewshed.visible_tiles.iter() {
+        let idx = map.xy_idx(tile.x, tile.y);
+        for entity in map.tile_content[idx].iter() {
+            if monsters.get(*entity).is_some() {
+                if let Some(name) = names.get(*entity) {
+                    return Some(format!("You see a {}.", name.name));
+                }
+            }
+        }
+    }
+
+    None
+}
+
+fn explore_step(ecs: &World) -> Result<Command, Option<String>> {
+    let map = ecs.fetch::<Map>();
+    let player_pos = ecs.fetch::<Point>();
+    let nothing_left = Some("There is nothing left to explore.".to_string());
+
+    let starts: Vec<usize> = map
+        .revealed_tiles
+        .iter()
+        .enumerate()
+        .filter(|(_, revealed)| !**revealed)
+        .map(|(idx, _)| idx)
+        .collect();
+    if starts.is_empty() {
+        return Err(nothing_left);
+    }
+
+    let dijkstra = DijkstraMap::new(MAPWIDTH, MAPHEIGHT, &starts, &*map, EXPLORE_DEPTH);
+    let player_idx = map.xy_idx(player_pos.x, player_pos.y);
+    match DijkstraMap::find_lowest_exit(&dijkstra, player_idx, &*map) {
+        Some(next_idx) if dijkstra.map[next_idx] < dijkstra.map[player_idx] => {
+            Ok(step_to(&map, *player_pos, next_idx))
+        }
+        _ => Err(nothing_left),
+    }
+}
+
+fn travel_step(ecs: &World, destination: Point) -> Result<Command, Option<String>> {
+    let map = ecs.fetch::<Map>();
+    let player_pos = ecs.fetch::<Point>();
+
+    if *player_pos == destination {
+        return Err(Some("You have arrived.".to_string()));
+    }
+
+    let path = rltk::a_star_search(
+        map.xy_idx(player_pos.x, player_pos.y),
+        map.xy_idx(destination.x, destination.y),
+        &*map,
+    );
+    if !path.success || path.steps.len() < 2 {
+        return Err(Some("The way is blocked.".to_string()));
+    }
+
+    Ok(step_to(&map, *player_pos, path.steps[1]))
+}
+
+/// Keeps moving in one direction until the way ahead is blocked or the
+/// surroundings change shape, which happens at corridor branches, doorways
+/// and room entrances.
+fn run_step(ecs: &World, dx: i32, dy: i32) -> Result<Command, Option<String>> {
+    let open = {
+        let map = ecs.fetch::<Map>();
+        let player_pos = ecs.fetch::<Point>();
+
+        let (x, y) = (player_pos.x + dx, player_pos.y + dy);
+        if x < 1 || x > map.width - 1 || y < 1 || y > map.height - 1 {
+            return Err(None);
+        }
+        if map.blocked[map.xy_idx(x, y)] {
+            return Err(None);
+        }
+
+        open_neighbours(&map, *player_pos)
+    };
+
+    let mut auto_move = ecs.fetch_mut::<AutoMove>();
+    let changed = open != auto_move.open_neighbours;
+    auto_move.open_neighbours = open;
+    if auto_move.steps > 0 && changed {
+        return Err(None);
+    }
+
+    Ok(Command::Move { dx, dy })
+}
+
+fn open_neighbours(map: &Map, pos: Point) -> usize {
+    let mut open = 0;
+    for y in pos.y - 1..=pos.y + 1 {
+        for x in pos.x - 1..=pos.x + 1 {
+            if (x, y) != (pos.x, pos.y) && map.tiles[map.xy_idx(x, y)] != TileType::Wall {
+                open += 1;
+            }
+        }
+    }
+    open
+}
+
+fn step_to(map: &Map, from: Point, idx: usize) -> Command {
+    Command::Move {
+        dx: idx as i32 % map.width - from.x,
+        dy: idx as i32 / map.width - from.y,
+    }
+}
+
+/// Starts travelling to the down stairs, if they have been seen.
+pub fn travel_to_stairs(ecs: &mut World) {
+    let stairs = {
+        let map = ecs.fetch::<Map>();
+        map.tiles
+            .iter()
+            .enumerate()
+            .find(|(idx, tile)| **tile == TileType::DownStairs && map.revealed_tiles[*idx])
+            .map(|(idx, _)| Point::new(idx as i32 % map.width, idx as i32 / map.width))
+    };
+
+    match stairs {
+        Some(destination) => start(ecs, AutoMoveMode::Travel { destination }),
+        None => ecs
+            .fetch_mut::<GameLog>()
+            .entries
+            .push("You don't know where the stairs are.".to_string()),
+    }
+}
+
+/// Starts travelling to a clicked map tile, if it has been revealed.
+pub fn travel_to(ecs: &mut World, destination: Point) {
+    let known = {
+        let map = ecs.fetch::<Map>();
+        destination.x >= 0
+            && destination.x < map.width
+            && destination.y >= 0
+            && destination.y < map.height
+            && map.revealed_tiles[map.xy_idx(destination.x, destination.y)]
+    };
+
+    if known {
+        start(ecs, AutoMoveMode::Travel { destination });
+    }
+}
+
+fn items_in_view(ecs: &World) -> Vec<Entity> {
+    let player_entity = ecs.fetch::<Entity>();
+    let viewsheds = ecs.read_storage::<Viewshed>();
+    let items = ecs.read_storage::<Item>();
+    let map = ecs.fetch::<Map>();
+
+    let mut seen = Vec::new();
+    if let Some(viewshed) = viewsheds.get(*player_entity) {
+        for tile in viewshed.visible_tiles.iter() {
+            let idx = map.xy_idx(tile.x, tile.y);
+            for entity in map.tile_content[idx].iter() {
+                if items.get(*entity).is_some() {
+                    seen.push(*entity);
+                }
+            }
+        }
+    }
+    seen
+}
+
+fn player_hp(ecs: &World) -> i32 {
+    let player_entity = ecs.fetch::<Entity>();
+    ecs.read_storage::<CombatStats>()
+        .get(*player_entity)
+        .map_or(0, |stats| stats.hp)
+}
@@ -0,0 +1,63 @@
+use crate::{spawner, Boss, CombatStats, GameLog, Map, Name, Position, Viewshed};
+use rltk::Point;
+use specs::prelude::*;
+
+/// Turns between the boss's calls for help.
+pub const SUMMON_COOLDOWN: i32 = 6;
+
+/// The boss's special abilities, run once per monster turn: it regains a
+/// little health, and while it can see the player it summons a goblin to
+/// its side every few turns.
+pub fn boss_abilities(ecs: &mut World) {
+    let mut summons: Vec<(i32, i32)> = Vec::new();
+    {
+        let entities = ecs.entities();
+        let mut bosses = ecs.write_storage::<Boss>();
+        let mut stats = ecs.write_storage::<CombatStats>();
+        let positions = ecs.read_storage::<Position>();
+        let viewsheds = ecs.read_storage::<Viewshed>();
+        let names = ecs.read_storage::<Name>();
+        let player_pos = ecs.fetch::<Point>();
+        let map = ecs.fetch::<Map>();
+        let mut log = ecs.write_resource::<GameLog>();
+
+        for (entity, boss, stats, pos) in (&entities, &mut bosses, &mut stats, &positions).join() {
+            if stats.hp < 1 {
+                continue;
+            }
+            stats.hp = i32::min(stats.hp + 1, stats.max_hp);
+
+            let sees_player = viewsheds
+                .get(entity)
+                .is_some_and(|viewshed| viewshed.visible_tiles.contains(&*player_pos));
+            if !sees_player {
+                continue;
+            }
+            boss.summon_cooldown -= 1;
+            if boss.summon_cooldown > 0 {
+                continue;
+            }
+            boss.summon_cooldown = SUMMON_COOLDOWN;
+
+            let free = (-1..=1)
+                .flat_map(|dy| (-1..=1).map(move |dx| (pos.x + dx, pos.y + dy)))
+                .find(|(x, y)| !map.blocked[map.xy_idx(*x, *y)]);
+            if let Some(spot) = free {
+                if let Some(name) = names.get(entity) {
+                    log.entries.push(format!(
+                        "The {} bellows, and a goblin answers the call!",
+                        name.name
+                    ));
+                }
+                summons.push(spot);
+            }
+        }
+    }
+
+    for (x, y) in summons {
+        spawner::goblin(ecs, x, y);
+        let mut map = ecs.fetch_mut::<Map>();
+        let idx = map.xy_idx(x, y);
+        map.blocked[idx] = true;
+    }
+}
@@ -0,0 +1,214 @@
+use crate::identification::{Identification, SCROLLS};
+use crate::inventory_system::stack_order;
+use crate::{
+    spawner, Attributes, CombatStats, Equippable, Equipped, InBackpack, Name, Position, Rarity,
+    RarityTier, Wallet,
+};
+use rltk::RandomNumberGenerator;
+use serde::{Deserialize, Serialize};
+use specs::prelude::*;
+
+/// The longest name the creation screen will accept.
+pub const MAX_NAME_LENGTH: usize = 13;
+
+/// What every attribute starts at unless the player rolls for them.
+pub const STANDARD_ATTRIBUTE: i32 = 10;
+
+#[derive(Serialize, Deserialize, PartialEq, Copy, Clone, Debug)]
+pub enum CharacterClass {
+    Fighter,
+    Rogue,
+    Mage,
+}
+
+pub const CLASSES: &[CharacterClass] = &[
+    CharacterClass::Fighter,
+    CharacterClass::Rogue,
+    CharacterClass::Mage,
+];
+
+impl CharacterClass {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CharacterClass::Fighter => "Fighter",
+            CharacterClass::Rogue => "Rogue",
+            CharacterClass::Mage => "Mage",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            CharacterClass::Fighter => "Tough and hard-hitting. Starts armed and shielded.",
+            CharacterClass::Rogue => "Quick-witted, with a full purse and a few tricks.",
+            CharacterClass::Mage => "Frail, but knows every scroll and carries several.",
+        }
+    }
+
+    fn combat_stats(&self) -> CombatStats {
+        let (hp, defense, power) = match self {
+            CharacterClass::Fighter => (34, 2, 6),
+            CharacterClass::Rogue => (28, 1, 5),
+            CharacterClass::Mage => (22, 0, 4),
+        };
+        CombatStats {
+            max_hp: hp,
+            hp,
+            defense,
+            power,
+        }
+    }
+
+    fn starting_gear(&self) -> &'static [&'static str] {
+        match self {
+            CharacterClass::Fighter => &["Dagger", "Shield", "Health Potion"],
+            CharacterClass::Rogue => &[
+                "Dagger",
+                "Health Potion",
+                "Health Potion",
+                "Confusion Scroll",
+            ],
+            CharacterClass::Mage => &[
+                "Magic Missile Scroll",
+                "Magic Missile Scroll",
+                "Magic Missile Scroll",
+                "Fireball Scroll",
+                "Health Potion",
+            ],
+        }
+    }
+
+    fn starting_gold(&self) -> i32 {
+        match self {
+            CharacterClass::Fighter => 0,
+            CharacterClass::Rogue => 40,
+            CharacterClass::Mage => 10,
+        }
+    }
+}
+
+/// Everything chosen on the character creation screen. It's kept with the
+/// replay, alongside the seed, so a replay rebuilds the same character.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CharacterSheet {
+    pub name: String,
+    pub class: CharacterClass,
+    pub strength: i32,
+    pub toughness: i32,
+}
+
+impl Default for CharacterSheet {
+    fn default() -> CharacterSheet {
+        CharacterSheet {
+            name: "Adventurer".to_string(),
+            class: CharacterClass::Fighter,
+            strength: STANDARD_ATTRIBUTE,
+            toughness: STANDARD_ATTRIBUTE,
+        }
+    }
+}
+
+impl CharacterSheet {
+    /// Rolls 3d6 for each attribute in place of the standard values.
+    pub fn roll_attributes(&mut self, rng: &mut RandomNumberGenerator) {
+        self.strength = rng.roll_dice(3, 6);
+        self.toughness = rng.roll_dice(3, 6);
+    }
+
+    /// Extra (or fewer) hit points from toughness.
+    pub fn hp_bonus(&self) -> i32 {
+        self.toughness - STANDARD_ATTRIBUTE
+    }
+}
+
+/// Creates the player as described by `sheet`, with their class's stats and
+/// kit. Starting equipment is worn straight away unless it turns out to be
+/// cursed.
+pub fn create_player(ecs: &mut World, sheet: &CharacterSheet, x: i32, y: i32) -> Entity {
+    let player = spawner::player(ecs, x, y);
+
+    let mut stats = sheet.class.combat_stats();
+    stats.max_hp = i32::max(1, stats.max_hp + sheet.hp_bonus());
+    stats.hp = stats.max_hp;
+    ecs.write_storage::<CombatStats>()
+        .insert(player, stats)
+        .expect("Unable to insert combat stats");
+    ecs.write_storage::<Name>()
+        .insert(
+            player,
+            Name {
+                name: sheet.name.clone(),
+            },
+        )
+        .expect("Unable to insert name");
+    ecs.write_storage::<Attributes>()
+        .insert(
+            player,
+            Attributes {
+                strength: sheet.strength,
+            },
+        )
+        .expect("Unable to insert attributes");
+    ecs.write_storage::<Wallet>()
+        .insert(
+            player,
+            Wallet {
+                gold: sheet.class.starting_gold(),
+            },
+        )
+        .expect("Unable to insert wallet");
+
+    for name in sheet.class.starting_gear() {
+        if let Some(item) = spawner::spawn_item(ecs, name, x, y) {
+            give_to(ecs, player, item);
+        }
+    }
+
+    if sheet.class == CharacterClass::Mage {
+        ecs.fetch_mut::<Identification>()
+            .identified
+            .extend(SCROLLS.iter().map(|scroll| scroll.to_string()));
+    }
+
+    player
+}
+
+fn give_to(ecs: &mut World, owner: Entity, item: Entity) {
+    let entities = ecs.entities();
+    let names = ecs.read_storage::<Name>();
+    let rarities = ecs.read_storage::<Rarity>();
+    let equippables = ecs.read_storage::<Equippable>();
+    let mut backpack = ecs.write_storage::<InBackpack>();
+
+    let tier = rarities.get(item).map(|r| r.tier);
+    let order = stack_order(
+        (&entities, &backpack, &names)
+            .join()
+            .filter(|(_, pack, _)| pack.owner == owner)
+            .map(|(carried, pack, name)| {
+                (
+                    pack.order,
+                    name.name.as_str(),
+                    rarities.get(carried).map(|r| r.tier),
+                )
+            }),
+        &names.get(item).unwrap().name,
+        tier,
+        equippables.get(item).is_none(),
+    );
+    backpack
+        .insert(item, InBackpack { owner, order })
+        .expect("Unable to insert backpack entry");
+    ecs.write_storage::<Position>().remove(item);
+
+    if let (Some(equippable), false) = (equippables.get(item), tier == Some(RarityTier::Cursed)) {
+        ecs.write_storage::<Equipped>()
+            .insert(
+                item,
+                Equipped {
+                    owner,
+                    slot: equippable.slot,
+                },
+            )
+            .expect("Unable to equip item");
+    }
+}
@@ -1,16 +1,27 @@
+pub use crate::fov::FovAlgorithm;
+pub use crate::identification::Identification;
+use crate::score::RunStats;
 pub use crate::Map;
 use rltk::RGB;
 use serde::{Deserialize, Serialize};
 pub use specs::error::NoError;
 use specs::prelude::*;
-pub use specs::saveload::{ConvertSaveload, Marker};
+pub use specs::saveload::{ConvertSaveload, Marker, SimpleMarker};
 pub use specs_derive::{Component, ConvertSaveload};
 
 pub struct SerializeMe;
 
-#[derive(Component, ConvertSaveload, Clone)]
+#[derive(Component, Serialize, Deserialize, Clone)]
 pub struct SerializationHelper {
     pub map: Map,
+    #[serde(default)]
+    pub identification: Identification,
+    #[serde(default)]
+    pub stats: RunStats,
+    /// The run's seed, so scores and morgue files from a resumed game still
+    /// name the dungeon it was played in.
+    #[serde(default)]
+    pub seed: u64,
 }
 
 #[derive(Component, ConvertSaveload, Clone)]
@@ -36,6 +47,125 @@ pub struct InflictsDamage {
 #[derive(Component, Serialize, Deserialize, Clone)]
 pub struct Consumable {}
 
+#[derive(PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum RarityTier {
+    Common,
+    Magic,
+    Rare,
+    Cursed,
+}
+
+impl RarityTier {
+    /// Scales an item's main effect, such as healing or damage.
+    pub fn scale(&self, base: i32) -> i32 {
+        match self {
+            RarityTier::Common => base,
+            RarityTier::Magic => base * 3 / 2,
+            RarityTier::Rare => base * 2,
+            RarityTier::Cursed => base / 2,
+        }
+    }
+
+    /// Adjusts an equipment bonus; cursed gear works against its wearer.
+    pub fn bonus(&self, base: i32) -> i32 {
+        match self {
+            RarityTier::Common => base,
+            RarityTier::Magic => base + 1,
+            RarityTier::Rare => base + 2,
+            RarityTier::Cursed => -base,
+        }
+    }
+
+    pub fn colour(&self) -> RGB {
+        match self {
+            RarityTier::Common => RGB::named(rltk::WHITE),
+            RarityTier::Magic => RGB::named(rltk::SKYBLUE),
+            RarityTier::Rare => RGB::named(rltk::GOLD),
+            RarityTier::Cursed => RGB::named(rltk::RED),
+        }
+    }
+}
+
+#[derive(Component, Serialize, Deserialize, Clone)]
+pub struct Rarity {
+    pub tier: RarityTier,
+    /// The tier a cursed item passes for until the player puts it on.
+    #[serde(default)]
+    pub disguise: Option<RarityTier>,
+}
+
+impl Rarity {
+    /// The tier the player believes the item to be.
+    pub fn apparent_tier(&self) -> RarityTier {
+        self.disguise.unwrap_or(self.tier)
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum EquipmentSlot {
+    Melee,
+    Shield,
+}
+
+#[derive(Component, Serialize, Deserialize, Clone)]
+pub struct Equippable {
+    pub slot: EquipmentSlot,
+}
+
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct Equipped {
+    pub owner: Entity,
+    pub slot: EquipmentSlot,
+}
+
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct MeleePowerBonus {
+    /// Before the item's rarity is applied, so lifting a curse restores it.
+    pub power: i32,
+}
+
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct DefenseBonus {
+    /// Before the item's rarity is applied, so lifting a curse restores it.
+    pub defense: i32,
+}
+
+#[derive(Component, Serialize, Deserialize, Clone)]
+pub struct RemovesCurse {}
+
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct Wallet {
+    pub gold: i32,
+}
+
+/// A pile of coins; picking it up adds to the collector's wallet.
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct Gold {
+    pub amount: i32,
+}
+
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct Price {
+    pub value: i32,
+}
+
+impl Price {
+    /// What a vendor pays for the item.
+    pub fn sale_value(&self) -> i32 {
+        i32::max(1, self.value / 2)
+    }
+}
+
+/// Names the table in `spawner::loot_table` rolled when the owner dies.
+#[derive(Component, Serialize, Deserialize, Clone)]
+pub struct LootTable {
+    pub table: String,
+}
+
+/// Sells the items in its backpack and buys anything with a price.
+#[derive(Component, Serialize, Deserialize, Clone)]
+pub struct Vendor {}
+
 #[derive(Component, ConvertSaveload, Clone)]
 pub struct WantsToDropItem {
     pub item: Entity,
@@ -53,14 +183,72 @@ pub struct WantsToPickupItem {
     pub item: Entity,
 }
 
-#[derive(Component, ConvertSaveload, Clone)]
+#[derive(Component, Clone)]
 pub struct InBackpack {
     pub owner: Entity,
+    pub order: i32,
+}
+
+/// `InBackpack` as saved. Written out by hand instead of derived so that
+/// saves from before backpacks were ordered still load.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(bound = "MA: Marker")]
+pub struct InBackpackSaveloadData<MA> {
+    pub owner: MA,
+    #[serde(default)]
+    pub order: i32,
+}
+
+impl<MA> ConvertSaveload<MA> for InBackpack
+where
+    MA: Marker + Serialize,
+    for<'de> MA: Deserialize<'de>,
+{
+    type Data = InBackpackSaveloadData<MA>;
+    type Error = NoError;
+
+    fn convert_into<F>(&self, ids: F) -> Result<Self::Data, Self::Error>
+    where
+        F: FnMut(Entity) -> Option<MA>,
+    {
+        Ok(InBackpackSaveloadData {
+            owner: self.owner.convert_into(ids)?,
+            order: self.order,
+        })
+    }
+
+    fn convert_from<F>(data: Self::Data, ids: F) -> Result<Self, Self::Error>
+    where
+        F: FnMut(MA) -> Option<Entity>,
+    {
+        Ok(InBackpack {
+            owner: Entity::convert_from(data.owner, ids)?,
+            order: data.order,
+        })
+    }
 }
 
 #[derive(Component, Serialize, Deserialize, Clone)]
 pub struct Item {}
 
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct Weight {
+    pub weight: i32,
+}
+
+pub const CARRY_WEIGHT_PER_STRENGTH: i32 = 5;
+
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct Attributes {
+    pub strength: i32,
+}
+
+impl Attributes {
+    pub fn carry_capacity(&self) -> i32 {
+        self.strength * CARRY_WEIGHT_PER_STRENGTH
+    }
+}
+
 #[derive(Component, ConvertSaveload, Clone)]
 pub struct ProvidesHealing {
     pub heal_amount: i32,
@@ -74,11 +262,13 @@ pub struct Renderable {
     pub render_order: i32,
 }
 
-#[derive(Component, ConvertSaveload, Clone)]
+#[derive(Component, Serialize, Deserialize, Clone)]
 pub struct Viewshed {
     pub visible_tiles: Vec<rltk::Point>,
     pub range: i32,
     pub dirty: bool,
+    #[serde(default)]
+    pub algorithm: FovAlgorithm,
 }
 
 #[derive(Component, Clone, ConvertSaveload)]
@@ -93,11 +283,42 @@ pub struct Player {}
 #[derive(Component, Serialize, Deserialize, Clone)]
 pub struct Monster {}
 
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct ProvidesFood {
+    pub nutrition: i32,
+}
+
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct LightSource {
+    pub radius: i32,
+}
+
+/// A monster clever enough to pick up, drink and throw items.
+#[derive(Component, Serialize, Deserialize, Clone)]
+pub struct UsesItems {}
+
+/// The unique monster guarding the final level. It heals over time, never
+/// runs away, and calls for help whenever `summon_cooldown` runs out while
+/// it can see the player.
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct Boss {
+    pub summon_cooldown: i32,
+}
+
+/// Carrying this item wins the game.
+#[derive(Component, Serialize, Deserialize, Clone)]
+pub struct GoalItem {}
+
 #[derive(Component, ConvertSaveload, Clone)]
 pub struct Name {
     pub name: String,
 }
 
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct Description {
+    pub description: String,
+}
+
 #[derive(Component, Serialize, Deserialize, Clone)]
 pub struct BlocksTile {}
 
@@ -109,6 +330,11 @@ pub struct CombatStats {
     pub power: i32,
 }
 
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct WantsToMove {
+    pub destination: rltk::Point,
+}
+
 #[derive(Component, ConvertSaveload, Clone)]
 pub struct WantsToMelee {
     pub target: Entity,
@@ -119,20 +345,83 @@ pub struct State {
     pub ecs: World,
 }
 
-#[derive(Component, ConvertSaveload, Clone)]
+#[derive(Component, Serialize, Deserialize, Clone)]
 pub struct SufferDamage {
     pub amount: Vec<i32>,
+    /// What dealt each hit in `amount`, for reporting how someone died.
+    #[serde(default)]
+    pub causes: Vec<String>,
+    /// True if the player dealt any of it, so a death counts as their kill.
+    #[serde(default)]
+    pub by_player: bool,
 }
 
 impl SufferDamage {
-    pub fn new_damage(store: &mut WriteStorage<SufferDamage>, victim: Entity, amount: i32) {
+    pub fn new_damage(
+        store: &mut WriteStorage<SufferDamage>,
+        victim: Entity,
+        amount: i32,
+        cause: String,
+        by_player: bool,
+    ) {
         if let Some(suffering) = store.get_mut(victim) {
             suffering.amount.push(amount);
+            suffering.causes.push(cause);
+            suffering.by_player |= by_player;
         } else {
             let dmg = SufferDamage {
                 amount: vec![amount],
+                causes: vec![cause],
+                by_player,
             };
             store.insert(victim, dmg).expect("Unable to insert damage");
         }
     }
 }
+
+/// Registers every component with a new world.
+pub fn register_components(ecs: &mut World) {
+    ecs.register::<Position>();
+    ecs.register::<Renderable>();
+    ecs.register::<Player>();
+    ecs.register::<Viewshed>();
+    ecs.register::<Monster>();
+    ecs.register::<Name>();
+    ecs.register::<Description>();
+    ecs.register::<Weight>();
+    ecs.register::<Attributes>();
+    ecs.register::<Rarity>();
+    ecs.register::<Equippable>();
+    ecs.register::<Equipped>();
+    ecs.register::<MeleePowerBonus>();
+    ecs.register::<DefenseBonus>();
+    ecs.register::<RemovesCurse>();
+    ecs.register::<Wallet>();
+    ecs.register::<Gold>();
+    ecs.register::<Price>();
+    ecs.register::<Vendor>();
+    ecs.register::<LootTable>();
+    ecs.register::<UsesItems>();
+    ecs.register::<ProvidesFood>();
+    ecs.register::<LightSource>();
+    ecs.register::<Boss>();
+    ecs.register::<GoalItem>();
+    ecs.register::<BlocksTile>();
+    ecs.register::<CombatStats>();
+    ecs.register::<WantsToMelee>();
+    ecs.register::<WantsToMove>();
+    ecs.register::<SufferDamage>();
+    ecs.register::<Item>();
+    ecs.register::<ProvidesHealing>();
+    ecs.register::<WantsToPickupItem>();
+    ecs.register::<WantsToDropItem>();
+    ecs.register::<InBackpack>();
+    ecs.register::<WantsToUseItem>();
+    ecs.register::<Consumable>();
+    ecs.register::<Ranged>();
+    ecs.register::<InflictsDamage>();
+    ecs.register::<AreaOfEffect>();
+    ecs.register::<Confusion>();
+    ecs.register::<SimpleMarker<SerializeMe>>();
+    ecs.register::<SerializationHelper>();
+}
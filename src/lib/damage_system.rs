@@ -1,19 +1,51 @@
-use super::{CombatStats, GameLog, Name, Player, SufferDamage};
-use rltk::console;
+use super::{
+    spawner, CombatStats, Equipped, GameLog, InBackpack, LootTable, Map, Monster, Name, Player,
+    Position, Renderable, RunState, RunStats, SufferDamage,
+};
+use rltk::RandomNumberGenerator;
 use specs::prelude::*;
 
 pub struct DamageSystem {}
 
 impl<'a> System<'a> for DamageSystem {
     type SystemData = (
+        Entities<'a>,
         WriteStorage<'a, CombatStats>,
         WriteStorage<'a, SufferDamage>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, Monster>,
+        WriteExpect<'a, Map>,
+        ReadExpect<'a, Entity>,
+        WriteExpect<'a, RunStats>,
     );
     fn run(&mut self, data: Self::SystemData) {
-        let (mut stats, mut damage) = data;
+        let (
+            entities,
+            mut stats,
+            mut damage,
+            positions,
+            monsters,
+            mut map,
+            player_entity,
+            mut run_stats,
+        ) = data;
 
-        for (mut stats, damage) in (&mut stats, &damage).join() {
-            stats.hp -= damage.amount.iter().sum::<i32>();
+        for (entity, stats, damage, pos) in
+            (&entities, &mut stats, &damage, (&positions).maybe()).join()
+        {
+            let amount = damage.amount.iter().sum::<i32>();
+            let was_alive = stats.hp > 0;
+            stats.hp -= amount;
+            if let (true, Some(pos)) = (amount > 0, pos) {
+                let idx = map.xy_idx(pos.x, pos.y);
+                map.bloodstains.insert(idx);
+            }
+            if entity == *player_entity && was_alive && stats.hp < 1 {
+                run_stats.cause_of_death = damage.causes.last().cloned();
+            }
+            if monsters.get(entity).is_some() && was_alive && stats.hp < 1 && damage.by_player {
+                run_stats.kills += 1;
+            }
         }
         damage.clear();
     }
@@ -37,13 +69,123 @@ pub fn delete_the_dead(ecs: &mut World) {
                         }
                         dead.push(entity)
                     }
-                    Some(_) => console::log("you are dead"),
+                    Some(_) => {
+                        let mut runstate = ecs.write_resource::<RunState>();
+                        if *runstate != RunState::GameOver {
+                            log.entries.push("You are dead.".to_string());
+                            *runstate = RunState::GameOver;
+                        }
+                    }
                 }
             }
         }
     }
 
     for victim in dead {
+        leave_corpse(ecs, victim);
+        drop_loot(ecs, victim);
         ecs.delete_entity(victim).expect("Unable to delete");
     }
 }
+
+fn leave_corpse(ecs: &mut World, victim: Entity) {
+    let remains = {
+        let positions = ecs.read_storage::<Position>();
+        let names = ecs.read_storage::<Name>();
+        let renderables = ecs.read_storage::<Renderable>();
+        match (
+            positions.get(victim),
+            names.get(victim),
+            renderables.get(victim),
+        ) {
+            (Some(pos), Some(name), Some(render)) => {
+                Some((pos.x, pos.y, name.name.clone(), render.glyph))
+            }
+            _ => None,
+        }
+    };
+
+    if let Some((x, y, name, glyph)) = remains {
+        spawner::corpse(ecs, x, y, &name, glyph);
+    }
+}
+
+/// Leaves everything the victim carried, plus a roll on its loot table,
+/// where it fell.
+fn drop_loot(ecs: &mut World, victim: Entity) {
+    let pos = match ecs.read_storage::<Position>().get(victim) {
+        Some(pos) => pos.clone(),
+        None => return,
+    };
+
+    let carried: Vec<Entity> = {
+        let entities = ecs.entities();
+        let backpack = ecs.read_storage::<InBackpack>();
+        (&entities, &backpack)
+            .join()
+            .filter(|(_, pack)| pack.owner == victim)
+            .map(|(item, _)| item)
+            .collect()
+    };
+    for item in carried {
+        ecs.write_storage::<InBackpack>().remove(item);
+        ecs.write_storage::<Equipped>().remove(item);
+        ecs.write_storage::<Position>()
+            .insert(item, pos.clone())
+            .expect("Unable to insert position");
+    }
+
+    let table = ecs
+        .read_storage::<LootTable>()
+        .get(victim)
+        .map(|loot| loot.table.clone());
+    if let Some(table) = table {
+        let roll =
+            spawner::loot_table(&table).roll(&mut ecs.write_resource::<RandomNumberGenerator>());
+        spawner::spawn_item(ecs, &roll, pos.x, pos.y);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::register_components;
+
+    /// Kills counted after a monster takes a fatal hit, from the player or
+    /// not.
+    fn kills_after_fatal_hit(by_player: bool) -> i32 {
+        let mut ecs = World::new();
+        register_components(&mut ecs);
+        ecs.insert(Map::default());
+        ecs.insert(RunStats::default());
+        let player = ecs.create_entity().with(Player {}).build();
+        ecs.insert(player);
+        let goblin = ecs
+            .create_entity()
+            .with(Monster {})
+            .with(CombatStats {
+                max_hp: 5,
+                hp: 5,
+                defense: 0,
+                power: 1,
+            })
+            .build();
+        SufferDamage::new_damage(
+            &mut ecs.write_storage::<SufferDamage>(),
+            goblin,
+            8,
+            "Fireball".to_string(),
+            by_player,
+        );
+
+        DamageSystem {}.run_now(&ecs);
+        let kills = ecs.fetch::<RunStats>().kills;
+        kills
+    }
+
+    #[test]
+    fn only_the_players_kills_count() {
+        assert_eq!(kills_after_fatal_hit(true), 1);
+        assert_eq!(kills_after_fatal_hit(false), 0);
+    }
+}
@@ -0,0 +1,269 @@
+use crate::map::Map;
+use rltk::{BaseMap, Point};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// How a viewshed works out what it can see.
+#[derive(PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum FovAlgorithm {
+    /// rltk's own scan. Fast, but not symmetric: a monster can sometimes
+    /// see the player without being seen.
+    Rltk,
+    /// Symmetric shadowcasting: if A can see B, B can see A.
+    Symmetric,
+    /// Sees a tile if any line from the viewer's cell reaches any part of
+    /// it, which lets you peek further round corners and pillars.
+    Permissive,
+}
+
+/// What viewsheds use unless told otherwise, including those loaded from
+/// saves made before there was a choice.
+impl Default for FovAlgorithm {
+    fn default() -> FovAlgorithm {
+        FovAlgorithm::Symmetric
+    }
+}
+
+/// Every tile visible from `origin` within `range`, including walls, with
+/// the origin itself always visible.
+pub fn field_of_view(algorithm: FovAlgorithm, origin: Point, range: i32, map: &Map) -> Vec<Point> {
+    match algorithm {
+        FovAlgorithm::Rltk => rltk::field_of_view(origin, range, map),
+        FovAlgorithm::Symmetric => symmetric_shadowcasting(origin, range, map),
+        FovAlgorithm::Permissive => permissive(origin, range, map),
+    }
+}
+
+fn in_bounds(map: &Map, p: Point) -> bool {
+    p.x >= 0 && p.x < map.width && p.y >= 0 && p.y < map.height
+}
+
+fn in_range(origin: Point, p: Point, range: i32) -> bool {
+    let (dx, dy) = (p.x - origin.x, p.y - origin.y);
+    dx * dx + dy * dy <= range * range
+}
+
+/// Out-of-bounds tiles count as walls so scans stop at the map edge.
+fn is_opaque(map: &Map, p: Point) -> bool {
+    !in_bounds(map, p) || map.is_opaque(map.xy_idx(p.x, p.y))
+}
+
+/// A slope `num / den` between a row's centre line and a tile edge, kept
+/// as a fraction so the symmetry check is exact.
+#[derive(Copy, Clone)]
+struct Slope {
+    num: i32,
+    den: i32,
+}
+
+struct Row {
+    depth: i32,
+    start: Slope,
+    end: Slope,
+}
+
+impl Row {
+    /// The columns this row covers: `depth * start` rounded half up to
+    /// `depth * end` rounded half down.
+    fn columns(&self) -> std::ops::RangeInclusive<i32> {
+        let min = (2 * self.depth * self.start.num + self.start.den).div_euclid(2 * self.start.den);
+        let max = -(self.end.den - 2 * self.depth * self.end.num).div_euclid(2 * self.end.den);
+        min..=max
+    }
+
+    fn next(&self) -> Row {
+        Row {
+            depth: self.depth + 1,
+            start: self.start,
+            end: self.end,
+        }
+    }
+
+    /// Floor tiles are only visible if their centre lies within the row's
+    /// slopes, which is what makes the scan symmetric.
+    fn is_symmetric(&self, col: i32) -> bool {
+        col * self.start.den >= self.depth * self.start.num
+            && col * self.end.den <= self.depth * self.end.num
+    }
+}
+
+fn tile_slope(depth: i32, col: i32) -> Slope {
+    Slope {
+        num: 2 * col - 1,
+        den: 2 * depth,
+    }
+}
+
+struct Quadrant {
+    origin: Point,
+    cardinal: usize,
+}
+
+impl Quadrant {
+    fn transform(&self, depth: i32, col: i32) -> Point {
+        let o = self.origin;
+        match self.cardinal {
+            0 => Point::new(o.x + col, o.y - depth),
+            1 => Point::new(o.x + depth, o.y + col),
+            2 => Point::new(o.x + col, o.y + depth),
+            _ => Point::new(o.x - depth, o.y + col),
+        }
+    }
+}
+
+fn symmetric_shadowcasting(origin: Point, range: i32, map: &Map) -> Vec<Point> {
+    let mut visible = HashSet::new();
+    visible.insert(origin);
+
+    for cardinal in 0..4 {
+        let quadrant = Quadrant { origin, cardinal };
+        let first = Row {
+            depth: 1,
+            start: Slope { num: -1, den: 1 },
+            end: Slope { num: 1, den: 1 },
+        };
+        scan(&quadrant, first, range, map, &mut visible);
+    }
+
+    visible.into_iter().collect()
+}
+
+fn scan(quadrant: &Quadrant, mut row: Row, range: i32, map: &Map, visible: &mut HashSet<Point>) {
+    if row.depth > range {
+        return;
+    }
+
+    let mut prev_opaque: Option<bool> = None;
+    for col in row.columns() {
+        let tile = quadrant.transform(row.depth, col);
+        let opaque = is_opaque(map, tile);
+
+        if (opaque || row.is_symmetric(col))
+            && in_bounds(map, tile)
+            && in_range(quadrant.origin, tile, range)
+        {
+            visible.insert(tile);
+        }
+        if prev_opaque == Some(true) && !opaque {
+            row.start = tile_slope(row.depth, col);
+        }
+        if prev_opaque == Some(false) && opaque {
+            let mut next = row.next();
+            next.end = tile_slope(row.depth, col);
+            scan(quadrant, next, range, map, visible);
+        }
+        prev_opaque = Some(opaque);
+    }
+
+    if prev_opaque == Some(false) {
+        scan(quadrant, row.next(), range, map, visible);
+    }
+}
+
+/// Points inside a cell, relative to its centre, that sight lines are
+/// drawn to and from. Kept just inside the edges so lines grazing a wall
+/// corner don't count as passing through it.
+const CELL_POINTS: [(f32, f32); 5] = [
+    (0.0, 0.0),
+    (-0.45, -0.45),
+    (0.45, -0.45),
+    (-0.45, 0.45),
+    (0.45, 0.45),
+];
+
+fn permissive(origin: Point, range: i32, map: &Map) -> Vec<Point> {
+    let mut visible = vec![origin];
+
+    for y in origin.y - range..=origin.y + range {
+        for x in origin.x - range..=origin.x + range {
+            let target = Point::new(x, y);
+            if target == origin || !in_bounds(map, target) || !in_range(origin, target, range) {
+                continue;
+            }
+            let seen = CELL_POINTS.iter().any(|from| {
+                CELL_POINTS
+                    .iter()
+                    .any(|to| line_is_clear(map, origin, *from, target, *to))
+            });
+            if seen {
+                visible.push(target);
+            }
+        }
+    }
+
+    visible
+}
+
+/// Walks a line between points in two cells and checks that every cell it
+/// crosses in between is transparent.
+fn line_is_clear(map: &Map, a: Point, from: (f32, f32), b: Point, to: (f32, f32)) -> bool {
+    let (x0, y0) = (a.x as f32 + from.0, a.y as f32 + from.1);
+    let (x1, y1) = (b.x as f32 + to.0, b.y as f32 + to.1);
+    let steps = (f32::max((x1 - x0).abs(), (y1 - y0).abs()) * 4.0).ceil() as i32;
+
+    for step in 1..steps {
+        let t = step as f32 / steps as f32;
+        let cell = Point::new(
+            (x0 + (x1 - x0) * t).round() as i32,
+            (y0 + (y1 - y0) * t).round() as i32,
+        );
+        if cell != a && cell != b && is_opaque(map, cell) {
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::TileType;
+    use rltk::RandomNumberGenerator;
+    use std::collections::HashMap;
+
+    const RANGE: i32 = 8;
+
+    /// Every pair of floor tiles on a few generated maps: A sees B exactly
+    /// when B sees A.
+    fn assert_symmetric(algorithm: FovAlgorithm) {
+        for seed in 1..=3 {
+            let (map, _) = Map::new_map(1, &mut RandomNumberGenerator::seeded(seed));
+            let floors: Vec<Point> = (0..map.height)
+                .flat_map(|y| (0..map.width).map(move |x| Point::new(x, y)))
+                .filter(|p| map.tiles[map.xy_idx(p.x, p.y)] != TileType::Wall)
+                .collect();
+            let seen: HashMap<Point, HashSet<Point>> = floors
+                .iter()
+                .map(|p| {
+                    let visible = field_of_view(algorithm, *p, RANGE, &map);
+                    (*p, visible.into_iter().collect())
+                })
+                .collect();
+
+            for a in floors.iter() {
+                for b in floors.iter() {
+                    assert_eq!(
+                        seen[a].contains(b),
+                        seen[b].contains(a),
+                        "seed {}: ({}, {}) and ({}, {})",
+                        seed,
+                        a.x,
+                        a.y,
+                        b.x,
+                        b.y
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn symmetric_shadowcasting_is_symmetric() {
+        assert_symmetric(FovAlgorithm::Symmetric);
+    }
+
+    #[test]
+    fn permissive_is_symmetric() {
+        assert_symmetric(FovAlgorithm::Permissive);
+    }
+}
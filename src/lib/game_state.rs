@@ -1,11 +1,16 @@
+use crate::auto_move;
+use crate::character::{self, CharacterSheet};
 use crate::components::State;
 use crate::damage_system::*;
 use crate::inventory_system::{ItemCollectionSystem, ItemDropSystem, ItemUseSystem};
+use crate::lighting_system::LightingSystem;
 use crate::map::draw_map;
 use crate::map_indexing_system::MapIndexingSystem;
 use crate::melee_combat_system::MeleeCombatSystem;
 use crate::monster_ai_system::MonsterAI;
-use crate::player::player_input;
+use crate::movement_system::MovementSystem;
+use crate::player::{perform_command, player_input};
+use crate::replay::{self, Command, Replay};
 use crate::saveload_system;
 use crate::visibility_system::VisibilitySystem;
 use crate::*;
@@ -22,11 +27,17 @@ impl State {
             let map = self.ecs.fetch::<Map>();
 
             let mut data = (&positions, &renderables).join().collect::<Vec<_>>();
-            data.sort_by(|&a, &b| b.1.render_order.cmp(&a.1.render_order));
+            data.sort_by_key(|&a| std::cmp::Reverse(a.1.render_order));
             for (pos, render) in data.iter() {
                 let idx = map.xy_idx(pos.x, pos.y);
                 if map.visible_tiles[idx] {
-                    ctx.set(pos.x, pos.y, render.fg, render.bg, render.glyph);
+                    // Keep decals visible underneath whatever stands on them.
+                    let bg = if map.bloodstains.contains(&idx) {
+                        map.background(idx)
+                    } else {
+                        render.bg
+                    };
+                    ctx.set(pos.x, pos.y, render.fg, bg, render.glyph);
                 }
             }
 
@@ -41,7 +52,10 @@ impl GameState for State {
         let oldrunstate = *self.ecs.fetch::<RunState>();
 
         match oldrunstate {
-            RunState::MainMenu { .. } => (),
+            RunState::MainMenu { .. }
+            | RunState::CharacterCreation { .. }
+            | RunState::Victory
+            | RunState::GameOver => (),
             _ => {
                 self.game_screen(ctx);
             }
@@ -53,23 +67,71 @@ impl GameState for State {
                     menu_selection: selected,
                 },
                 gui::MainMenuResult::Selected { selected } => match selected {
-                    gui::MainMenuSelection::NewGame => RunState::PreRun,
+                    gui::MainMenuSelection::NewGame => {
+                        self.ecs.remove::<saveload_system::LoadFailed>();
+                        let mut replay = self.ecs.fetch_mut::<Replay>();
+                        replay.seed = rltk::RandomNumberGenerator::new().next_u64();
+                        replay.replaying = false;
+                        replay.character = CharacterSheet {
+                            name: String::new(),
+                            ..CharacterSheet::default()
+                        };
+                        RunState::CharacterCreation {
+                            step: gui::CreationStep::Name,
+                        }
+                    }
                     gui::MainMenuSelection::LoadGame => {
-                        saveload_system::load_game(&mut self.ecs);
-                        saveload_system::delete_save();
-                        RunState::AwaitingInput
+                        match saveload_system::load_game(&mut self.ecs) {
+                            Ok(()) => {
+                                self.ecs.remove::<saveload_system::LoadFailed>();
+                                {
+                                    // A loaded game didn't start from the
+                                    // recorded seed, so it can't be replayed.
+                                    let mut replay = self.ecs.fetch_mut::<Replay>();
+                                    replay.replaying = false;
+                                    replay.recording = false;
+                                }
+                                saveload_system::delete_save();
+                                RunState::PreRun
+                            }
+                            Err(e) => {
+                                rltk::console::log(format!("Unable to load the game: {}", e));
+                                self.new_game();
+                                self.ecs.insert(saveload_system::LoadFailed);
+                                RunState::MainMenu {
+                                    menu_selection: gui::MainMenuSelection::NewGame,
+                                }
+                            }
+                        }
                     }
                     gui::MainMenuSelection::Quit => {
                         ::std::process::exit(0);
                     }
                 },
             },
+            RunState::CharacterCreation { mut step } => {
+                match gui::character_creation(self, ctx, &mut step) {
+                    gui::ItemMenuResult::Cancel => RunState::MainMenu {
+                        menu_selection: gui::MainMenuSelection::NewGame,
+                    },
+                    gui::ItemMenuResult::NoResponse => RunState::CharacterCreation { step },
+                    gui::ItemMenuResult::Selected => {
+                        self.new_game();
+                        replay::start_recording(&mut self.ecs);
+                        RunState::PreRun
+                    }
+                }
+            }
             RunState::NextLevel => {
                 self.goto_next_level();
+                self.autosave();
                 RunState::PreRun
             }
             RunState::SaveGame => {
-                saveload_system::save_game(&mut self.ecs);
+                // Like autosaves, a replay must never overwrite the real save.
+                if !self.ecs.fetch::<Replay>().replaying {
+                    saveload_system::save_game(&mut self.ecs);
+                }
 
                 RunState::MainMenu {
                     menu_selection: gui::MainMenuSelection::LoadGame,
@@ -79,73 +141,170 @@ impl GameState for State {
                 self.run_systems();
                 RunState::AwaitingInput
             }
-            RunState::AwaitingInput => player_input(self, ctx),
+            RunState::AwaitingInput => {
+                let replayed = self.ecs.fetch_mut::<Replay>().next_command();
+                match replayed {
+                    Some(command) => perform_command(&mut self.ecs, command),
+                    None => auto_move::auto_move_input(&mut self.ecs, ctx)
+                        .unwrap_or_else(|| player_input(self, ctx)),
+                }
+            }
             RunState::PlayerTurn => {
                 self.run_systems();
-                RunState::MonsterTurn
+                if score::has_won(&self.ecs) {
+                    self.end_run(true);
+                    RunState::Victory
+                } else {
+                    RunState::MonsterTurn
+                }
             }
             RunState::MonsterTurn => {
                 self.run_systems();
+                boss::boss_abilities(&mut self.ecs);
+                self.ecs.fetch_mut::<RunStats>().turns += 1;
+                let autosave_due = self
+                    .ecs
+                    .fetch_mut::<saveload_system::AutosaveSettings>()
+                    .tick();
+                if autosave_due {
+                    self.autosave();
+                }
                 RunState::AwaitingInput
             }
-            RunState::ShowInventory => {
-                let result = gui::show_inventory(self, ctx);
+            RunState::ShowInventory { mut page } => {
+                let result = gui::show_inventory(self, ctx, &mut page);
                 match result.0 {
                     gui::ItemMenuResult::Cancel => RunState::AwaitingInput,
-                    gui::ItemMenuResult::NoResponse => RunState::ShowInventory,
+                    gui::ItemMenuResult::NoResponse => RunState::ShowInventory { page },
                     gui::ItemMenuResult::Selected => {
                         let item_entity = result.1.unwrap();
-                        let is_ranged = self.ecs.read_storage::<Ranged>();
-                        if let Some(ranged_item) = is_ranged.get(item_entity) {
+                        let ranged_range = self
+                            .ecs
+                            .read_storage::<Ranged>()
+                            .get(item_entity)
+                            .map(|ranged_item| ranged_item.range);
+                        if let Some(range) = ranged_range {
+                            let player_pos = *self.ecs.fetch::<Point>();
+                            let cursor = gui::targets_in_range(&self.ecs, range)
+                                .first()
+                                .copied()
+                                .unwrap_or(player_pos);
                             RunState::ShowTargeting {
-                                range: ranged_item.range,
+                                range,
                                 item: item_entity,
+                                cursor,
+                                last_mouse: ctx.mouse_point(),
                             }
                         } else {
-                            let mut intent = self.ecs.write_storage::<WantsToUseItem>();
-                            intent
-                                .insert(
-                                    *self.ecs.fetch::<Entity>(),
-                                    WantsToUseItem {
-                                        item: item_entity,
-                                        target: None,
-                                    },
-                                )
-                                .expect("Unable to insert intent");
-                            RunState::PlayerTurn
+                            perform_command(
+                                &mut self.ecs,
+                                Command::UseItem {
+                                    item: item_entity.id(),
+                                    target: None,
+                                },
+                            )
                         }
                     }
                 }
             }
-            RunState::ShowDropItem => {
-                let result = gui::drop_item_menu(self, ctx);
+            RunState::ShowDropItem { mut page } => {
+                let result = gui::drop_item_menu(self, ctx, &mut page);
                 match result.0 {
                     gui::ItemMenuResult::Cancel => RunState::AwaitingInput,
-                    gui::ItemMenuResult::NoResponse => RunState::ShowDropItem,
-                    gui::ItemMenuResult::Selected => {
-                        let item_entity = result.1.unwrap();
-                        let mut intent = self.ecs.write_storage::<WantsToDropItem>();
-                        intent
-                            .insert(
-                                *self.ecs.fetch::<Entity>(),
-                                WantsToDropItem { item: item_entity },
-                            )
-                            .expect("Unable to insert intent");
-                        RunState::PlayerTurn
+                    gui::ItemMenuResult::NoResponse => RunState::ShowDropItem { page },
+                    gui::ItemMenuResult::Selected => perform_command(
+                        &mut self.ecs,
+                        Command::DropItem {
+                            item: result.1.unwrap().id(),
+                        },
+                    ),
+                }
+            }
+            RunState::ShowLook { mut cursor } => match gui::look_mode(self, ctx, &mut cursor) {
+                gui::ItemMenuResult::NoResponse => RunState::ShowLook { cursor },
+                _ => RunState::AwaitingInput,
+            },
+            RunState::ShowPickup { mut selected } => {
+                match gui::pickup_menu(self, ctx, &mut selected) {
+                    (gui::ItemMenuResult::Cancel, _) => RunState::AwaitingInput,
+                    (gui::ItemMenuResult::NoResponse, _) => RunState::ShowPickup { selected },
+                    (gui::ItemMenuResult::Selected, items) => perform_command(
+                        &mut self.ecs,
+                        Command::PickUp {
+                            items: items.iter().map(|item| item.id()).collect(),
+                        },
+                    ),
+                }
+            }
+            RunState::ShowShop {
+                vendor,
+                mut mode,
+                mut page,
+            } => {
+                // Trades in a replay don't need the shop screen, so let the
+                // playback carry on.
+                if !self.ecs.fetch::<Replay>().playback.is_empty() {
+                    RunState::AwaitingInput
+                } else {
+                    match gui::shop_menu(self, ctx, vendor, &mut mode, &mut page) {
+                        (gui::ItemMenuResult::Cancel, _) => RunState::AwaitingInput,
+                        (gui::ItemMenuResult::NoResponse, _) => {
+                            RunState::ShowShop { vendor, mode, page }
+                        }
+                        (gui::ItemMenuResult::Selected, item) => {
+                            let vendor = vendor.id();
+                            let item = item.unwrap().id();
+                            let command = match mode {
+                                gui::ShopMode::Buy => Command::Buy { vendor, item },
+                                gui::ShopMode::Sell => Command::Sell { vendor, item },
+                            };
+                            perform_command(&mut self.ecs, command)
+                        }
                     }
                 }
             }
-            RunState::ShowTargeting { range, item } => match gui::ranged_target(self, ctx, range) {
-                (gui::ItemMenuResult::Cancel, _) => RunState::AwaitingInput,
-                (gui::ItemMenuResult::NoResponse, _) => RunState::ShowTargeting { range, item },
-                (gui::ItemMenuResult::Selected, target) => {
-                    let mut intent = self.ecs.write_storage::<WantsToUseItem>();
-                    intent
-                        .insert(*self.ecs.fetch::<Entity>(), WantsToUseItem { item, target })
-                        .expect("Unable to insert intent");
-                    RunState::PlayerTurn
+            RunState::Victory => match gui::victory_screen(&self.ecs, ctx) {
+                gui::ItemMenuResult::NoResponse => RunState::Victory,
+                _ => RunState::MainMenu {
+                    menu_selection: gui::MainMenuSelection::NewGame,
+                },
+            },
+            RunState::GameOver => match gui::game_over(&self.ecs, ctx) {
+                gui::ItemMenuResult::NoResponse => RunState::GameOver,
+                _ => {
+                    // Clear away the dead player so nothing keeps noticing
+                    // they've died while the menu is up.
+                    self.new_game();
+                    RunState::MainMenu {
+                        menu_selection: gui::MainMenuSelection::NewGame,
+                    }
                 }
             },
+            RunState::ShowHelp => match gui::show_help(self, ctx) {
+                gui::ItemMenuResult::NoResponse => RunState::ShowHelp,
+                _ => RunState::AwaitingInput,
+            },
+            RunState::ShowTargeting {
+                range,
+                item,
+                mut cursor,
+                mut last_mouse,
+            } => match gui::ranged_target(self, ctx, range, item, &mut cursor, &mut last_mouse) {
+                (gui::ItemMenuResult::Cancel, _) => RunState::AwaitingInput,
+                (gui::ItemMenuResult::NoResponse, _) => RunState::ShowTargeting {
+                    range,
+                    item,
+                    cursor,
+                    last_mouse,
+                },
+                (gui::ItemMenuResult::Selected, target) => perform_command(
+                    &mut self.ecs,
+                    Command::UseItem {
+                        item: item.id(),
+                        target,
+                    },
+                ),
+            },
         };
 
         {
@@ -154,20 +313,93 @@ impl GameState for State {
         }
 
         damage_system::delete_the_dead(&mut self.ecs);
+        if newrunstate != RunState::GameOver && *self.ecs.fetch::<RunState>() == RunState::GameOver
+        {
+            self.end_run(false);
+        }
+    }
+}
+
+/// Moves a resource that outlives a single game into the world for the next.
+fn carry_over<T: Send + Sync + 'static>(from: &mut World, to: &mut World) {
+    if let Some(resource) = from.remove::<T>() {
+        to.insert(resource);
     }
 }
 
 impl State {
-    fn run_systems(&mut self) {
-        let mut vis = VisibilitySystem {};
-        vis.run_now(&self.ecs);
+    /// Throws away whatever world there was and builds a fresh first level
+    /// and player from the replay's seed and character. The world is rebuilt
+    /// from scratch rather than emptied, so entities get the same ids
+    /// however many games came before and a replay can name them by id.
+    pub fn new_game(&mut self) {
+        let mut ecs = World::new();
+        register_components(&mut ecs);
+        ecs.insert(SimpleMarkerAllocator::<SerializeMe>::new());
+        carry_over::<Replay>(&mut self.ecs, &mut ecs);
+        carry_over::<RunState>(&mut self.ecs, &mut ecs);
+        carry_over::<saveload_system::AutosaveSettings>(&mut self.ecs, &mut ecs);
+        carry_over::<keymap::Keymap>(&mut self.ecs, &mut ecs);
+        carry_over::<score::HighScores>(&mut self.ecs, &mut ecs);
+        self.ecs = ecs;
 
+        let (seed, sheet) = {
+            let replay = self.ecs.fetch::<Replay>();
+            (replay.seed, replay.character.clone())
+        };
+        let mut rng = rltk::RandomNumberGenerator::seeded(seed);
+        let (map, room) = Map::new_map(1, &mut rng);
+        self.ecs
+            .insert(identification::Identification::new_game(&mut rng));
+        self.ecs.insert(rng);
+        self.ecs.insert(RunStats::default());
+        self.ecs.insert(auto_move::AutoMove::default());
+        self.ecs.insert(gamelog::GameLog {
+            entries: vec![
+                "Welcome to Rusty Roguelike".to_string(),
+                format!(
+                    "{} the {} sets out into the dungeon.",
+                    sheet.name,
+                    sheet.class.name()
+                ),
+            ],
+        });
+        self.ecs
+            .fetch_mut::<saveload_system::AutosaveSettings>()
+            .turns_since_save = 0;
+        let (px, py) = room.center();
+
+        for room in map.rooms.iter().skip(1) {
+            spawner::spawn_room(&mut self.ecs, room);
+        }
+        spawner::spawn_goodies(&mut self.ecs, map.rooms.first().unwrap());
+        spawner::spawn_vendor(&mut self.ecs, &map.rooms);
+
+        self.ecs.insert(map);
+
+        let player_entity = character::create_player(&mut self.ecs, &sheet, px, py);
+        self.ecs.insert(player_entity);
+
+        self.ecs.insert(Point::new(px, py));
+    }
+
+    fn run_systems(&mut self) {
         let mut mob = MonsterAI {};
         mob.run_now(&self.ecs);
 
+        let mut movement = MovementSystem {};
+        movement.run_now(&self.ecs);
+
         let mut mapindex = MapIndexingSystem {};
         mapindex.run_now(&self.ecs);
 
+        // After movement, so what's lit and seen is where everyone now is.
+        let mut lighting = LightingSystem {};
+        lighting.run_now(&self.ecs);
+
+        let mut vis = VisibilitySystem {};
+        vis.run_now(&self.ecs);
+
         let mut melee = MeleeCombatSystem {};
         melee.run_now(&self.ecs);
 
@@ -186,6 +418,32 @@ impl State {
         self.ecs.maintain();
     }
 
+    /// Writes up a finished run: its high score entry and morgue file. The
+    /// save goes too, since there is nothing left to resume. A replayed run
+    /// leaves all three alone, as the real run already wrote them.
+    fn end_run(&mut self, won: bool) {
+        if self.ecs.fetch::<Replay>().replaying {
+            return;
+        }
+        let entry = score::final_score(&self.ecs, won);
+        score::record_score(&entry);
+        self.ecs.insert(score::HighScores::load());
+        morgue::write_morgue(&self.ecs, &entry);
+        saveload_system::delete_save();
+    }
+
+    /// Saves the game, unless it's a replay: those must never overwrite the
+    /// player's real save.
+    fn autosave(&mut self) {
+        if self.ecs.fetch::<Replay>().replaying {
+            return;
+        }
+        saveload_system::save_game(&mut self.ecs);
+        self.ecs
+            .fetch_mut::<saveload_system::AutosaveSettings>()
+            .turns_since_save = 0;
+    }
+
     fn entities_to_remove_on_level_change(&mut self) -> Vec<Entity> {
         let entities = self.ecs.entities();
         let player = self.ecs.read_storage::<Player>();
@@ -221,7 +479,8 @@ impl State {
         let worldmap = {
             let mut worldmap_resource = self.ecs.write_resource::<Map>();
             let current_depth = worldmap_resource.depth;
-            let (newmap, _) = Map::new_map(current_depth + 1);
+            let mut rng = self.ecs.write_resource::<rltk::RandomNumberGenerator>();
+            let (newmap, _) = Map::new_map(current_depth + 1, &mut rng);
             *worldmap_resource = newmap;
             (*worldmap_resource).clone()
         };
@@ -229,6 +488,11 @@ impl State {
         for room in worldmap.rooms.iter().skip(1) {
             spawner::spawn_room(&mut self.ecs, room);
         }
+        if worldmap.depth == FINAL_DEPTH {
+            spawner::spawn_boss(&mut self.ecs, worldmap.rooms.last().unwrap());
+        } else {
+            spawner::spawn_vendor(&mut self.ecs, &worldmap.rooms);
+        }
 
         let (player_x, player_y) = worldmap.rooms[0].center();
         let mut player_position = self.ecs.write_resource::<Point>();
@@ -249,9 +513,335 @@ impl State {
         gamelog
             .entries
             .push("You descend to the next level and take a moment to heal".to_string());
+        if worldmap.depth == FINAL_DEPTH {
+            gamelog.entries.push(
+                "The air is thick with the stench of orcs. Their warlord is near.".to_string(),
+            );
+        }
         let mut player_health_store = self.ecs.write_storage::<CombatStats>();
         if let Some(player_health) = player_health_store.get_mut(*player_entity) {
             player_health.hp = i32::max(player_health.hp, player_health.max_hp / 2);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rltk::{DisplayConsole, SimpleConsole, VirtualKeyCode, BACKEND_INTERNAL, RGB};
+    use std::collections::VecDeque;
+    use std::sync::Once;
+
+    /// A terminal with no window behind it, enough to tick the game.
+    fn headless() -> Rltk {
+        static CONSOLE: Once = Once::new();
+        CONSOLE.call_once(|| {
+            BACKEND_INTERNAL.lock().consoles.push(DisplayConsole {
+                console: SimpleConsole::init(80, 50),
+                shader_index: 0,
+                font_index: 0,
+            });
+        });
+        Rltk {
+            width_pixels: 640,
+            height_pixels: 400,
+            original_height_pixels: 400,
+            original_width_pixels: 640,
+            fps: 0.0,
+            frame_time_ms: 0.0,
+            active_console: 0,
+            key: None,
+            mouse_pos: (0, 0),
+            left_click: false,
+            shift: false,
+            control: false,
+            alt: false,
+            web_button: None,
+            quitting: false,
+            post_scanlines: false,
+            post_screenburn: false,
+            screen_burn_color: RGB::named(rltk::CYAN),
+        }
+    }
+
+    /// Keeps anything the game writes out of the real save and score files.
+    fn in_scratch_dir() {
+        static SCRATCH: Once = Once::new();
+        SCRATCH.call_once(|| {
+            let dir = std::env::temp_dir().join(format!("blast-tests-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            std::env::set_current_dir(&dir).unwrap();
+        });
+    }
+
+    fn new_state(replay: Replay) -> State {
+        in_scratch_dir();
+        let mut gs = State { ecs: World::new() };
+        gs.ecs.insert(replay);
+        gs.ecs.insert(RunState::PreRun);
+        gs.ecs.insert(saveload_system::AutosaveSettings::new(
+            saveload_system::DEFAULT_AUTOSAVE_INTERVAL,
+        ));
+        gs.ecs.insert(keymap::Keymap::default());
+        gs.ecs.insert(score::HighScores {
+            entries: Vec::new(),
+        });
+        gs.new_game();
+        gs
+    }
+
+    fn runstate(gs: &State) -> RunState {
+        *gs.ecs.fetch::<RunState>()
+    }
+
+    /// Ticks until the game is waiting on the player and has nothing left
+    /// to replay.
+    fn run_until_input(gs: &mut State, ctx: &mut Rltk) {
+        for _ in 0..200 {
+            gs.tick(ctx);
+            if runstate(gs) == RunState::AwaitingInput
+                && gs.ecs.fetch::<Replay>().playback.is_empty()
+            {
+                return;
+            }
+        }
+        panic!("the game never came back for input");
+    }
+
+    /// The player's first carried item with the given component.
+    fn carried<T: Component>(gs: &State) -> Entity {
+        let player = *gs.ecs.fetch::<Entity>();
+        let entities = gs.ecs.entities();
+        let backpack = gs.ecs.read_storage::<InBackpack>();
+        let with = gs.ecs.read_storage::<T>();
+        (&entities, &backpack, &with)
+            .join()
+            .find(|(_, pack, _)| pack.owner == player)
+            .map(|(entity, _, _)| entity)
+            .unwrap()
+    }
+
+    type Snapshot = Vec<(u32, String, Option<(i32, i32)>, Option<i32>, Option<u32>)>;
+
+    /// Every named entity: its id, where it is, its health and who carries it.
+    fn snapshot(ecs: &World) -> Snapshot {
+        let entities = ecs.entities();
+        let names = ecs.read_storage::<Name>();
+        let positions = ecs.read_storage::<Position>();
+        let stats = ecs.read_storage::<CombatStats>();
+        let backpack = ecs.read_storage::<InBackpack>();
+        let mut snapshot: Snapshot = (&entities, &names)
+            .join()
+            .map(|(entity, name)| {
+                (
+                    entity.id(),
+                    name.name.clone(),
+                    positions.get(entity).map(|pos| (pos.x, pos.y)),
+                    stats.get(entity).map(|stats| stats.hp),
+                    backpack.get(entity).map(|pack| pack.owner.id()),
+                )
+            })
+            .collect();
+        snapshot.sort();
+        snapshot
+    }
+
+    #[test]
+    fn a_replay_rebuilds_the_recorded_run() {
+        let mut ctx = headless();
+
+        // Recorded the way the game does it: a first game behind the main
+        // menu, then the real one once the character has been created.
+        let mut recorded = new_state(Replay::new(7));
+        recorded.ecs.fetch_mut::<Replay>().character.name = "Tester".to_string();
+        recorded.new_game();
+        run_until_input(&mut recorded, &mut ctx);
+
+        let potion = carried::<ProvidesHealing>(&recorded);
+        let commands = vec![
+            Command::Move { dx: 1, dy: 0 },
+            Command::Wait,
+            Command::UseItem {
+                item: potion.id(),
+                target: None,
+            },
+            Command::Move { dx: -1, dy: 0 },
+            Command::DropItem {
+                item: carried::<Item>(&recorded).id(),
+            },
+            Command::Idle,
+        ];
+        recorded
+            .ecs
+            .fetch_mut::<Replay>()
+            .playback
+            .extend(commands.clone());
+        run_until_input(&mut recorded, &mut ctx);
+        assert!(!recorded.ecs.is_alive(potion), "the potion wasn't drunk");
+
+        let character = recorded.ecs.fetch::<Replay>().character.clone();
+        let mut replayed = new_state(Replay {
+            character,
+            replaying: true,
+            playback: VecDeque::from(commands),
+            ..Replay::new(7)
+        });
+        run_until_input(&mut replayed, &mut ctx);
+
+        assert_eq!(snapshot(&replayed.ecs), snapshot(&recorded.ecs));
+        assert_eq!(
+            replayed.ecs.fetch::<RunStats>().turns,
+            recorded.ecs.fetch::<RunStats>().turns
+        );
+    }
+
+    #[test]
+    fn saving_a_replay_leaves_the_save_alone() {
+        let mut ctx = headless();
+        let mut gs = new_state(Replay {
+            replaying: true,
+            ..Replay::new(3)
+        });
+        run_until_input(&mut gs, &mut ctx);
+
+        gs.ecs.insert(RunState::SaveGame);
+        gs.tick(&mut ctx);
+
+        assert!(!saveload_system::does_save_exist());
+        assert!(matches!(runstate(&gs), RunState::MainMenu { .. }));
+    }
+
+    #[test]
+    fn a_bound_key_takes_a_turn() {
+        let mut ctx = headless();
+        let mut gs = new_state(Replay::new(5));
+        run_until_input(&mut gs, &mut ctx);
+
+        ctx.key = Some(VirtualKeyCode::Numpad5);
+        gs.tick(&mut ctx);
+        assert!(runstate(&gs) == RunState::PlayerTurn);
+
+        ctx.key = None;
+        run_until_input(&mut gs, &mut ctx);
+        assert_eq!(gs.ecs.fetch::<RunStats>().turns, 1);
+    }
+
+    #[test]
+    fn the_player_sees_from_where_they_moved_to() {
+        let mut ctx = headless();
+        let mut gs = new_state(Replay::new(5));
+        run_until_input(&mut gs, &mut ctx);
+
+        let start = *gs.ecs.fetch::<Point>();
+        let (dx, dy) = [(1, 0), (-1, 0), (0, 1), (0, -1)]
+            .iter()
+            .copied()
+            .find(|(dx, dy)| {
+                let map = gs.ecs.fetch::<Map>();
+                !map.blocked[map.xy_idx(start.x + dx, start.y + dy)]
+            })
+            .unwrap();
+        gs.ecs
+            .fetch_mut::<Replay>()
+            .playback
+            .push_back(Command::Move { dx, dy });
+        gs.tick(&mut ctx);
+        gs.tick(&mut ctx);
+        assert!(runstate(&gs) == RunState::MonsterTurn);
+
+        let here = Point::new(start.x + dx, start.y + dy);
+        let player = *gs.ecs.fetch::<Entity>();
+        let viewsheds = gs.ecs.read_storage::<Viewshed>();
+        let viewshed = viewsheds.get(player).unwrap();
+        let map = gs.ecs.fetch::<Map>();
+        let mut seen = viewshed.visible_tiles.clone();
+        let mut expected = fov::field_of_view(viewshed.algorithm, here, viewshed.range, &map);
+        expected.retain(|p| map.light[map.xy_idx(p.x, p.y)] > 0.0);
+        seen.sort_by_key(|p| (p.x, p.y));
+        expected.sort_by_key(|p| (p.x, p.y));
+        assert_eq!(*gs.ecs.fetch::<Point>(), here);
+        assert!(!viewshed.dirty, "sight wasn't updated after the move");
+        assert_eq!(seen, expected);
+    }
+
+    #[test]
+    fn the_pickup_menu_takes_only_the_ticked_items() {
+        let mut ctx = headless();
+        let mut gs = new_state(Replay::new(5));
+        run_until_input(&mut gs, &mut ctx);
+
+        let here = *gs.ecs.fetch::<Point>();
+        let underfoot: Vec<Entity> = ["Apple", "Bone", "Candle", "Dice"]
+            .iter()
+            .map(|name| {
+                gs.ecs
+                    .create_entity()
+                    .with(Item {})
+                    .with(Position {
+                        x: here.x,
+                        y: here.y,
+                    })
+                    .with(Name {
+                        name: name.to_string(),
+                    })
+                    .build()
+            })
+            .collect();
+        gs.ecs.insert(RunState::ShowPickup { selected: 0 });
+
+        // Tick a, c and b, then untick b again.
+        for key in [
+            VirtualKeyCode::A,
+            VirtualKeyCode::C,
+            VirtualKeyCode::B,
+            VirtualKeyCode::B,
+            VirtualKeyCode::Return,
+        ] {
+            ctx.key = Some(key);
+            gs.tick(&mut ctx);
+        }
+        ctx.key = None;
+        run_until_input(&mut gs, &mut ctx);
+
+        let player = *gs.ecs.fetch::<Entity>();
+        let backpack = gs.ecs.read_storage::<InBackpack>();
+        let taken: Vec<bool> = underfoot
+            .iter()
+            .map(|item| backpack.get(*item).is_some_and(|pack| pack.owner == player))
+            .collect();
+        assert_eq!(taken, [true, false, true, false]);
+    }
+
+    #[test]
+    fn an_unbound_key_waits_for_the_player() {
+        let mut ctx = headless();
+        let mut gs = new_state(Replay::new(5));
+        run_until_input(&mut gs, &mut ctx);
+
+        for key in [
+            VirtualKeyCode::LShift,
+            VirtualKeyCode::LControl,
+            VirtualKeyCode::F12,
+        ] {
+            ctx.key = Some(key);
+            ctx.shift = key == VirtualKeyCode::LShift;
+            ctx.control = key == VirtualKeyCode::LControl;
+            gs.tick(&mut ctx);
+            assert!(runstate(&gs) == RunState::AwaitingInput, "{:?}", key);
+        }
+        ctx.key = None;
+        gs.tick(&mut ctx);
+        assert_eq!(gs.ecs.fetch::<RunStats>().turns, 0);
+    }
+
+    #[test]
+    fn no_key_waits_for_the_player() {
+        let mut ctx = headless();
+        let mut gs = new_state(Replay::new(5));
+        run_until_input(&mut gs, &mut ctx);
+
+        gs.tick(&mut ctx);
+        assert!(runstate(&gs) == RunState::AwaitingInput);
+        assert_eq!(gs.ecs.fetch::<RunStats>().turns, 0);
+    }
+}
@@ -1,6 +1,13 @@
+use crate::character::{CharacterSheet, CLASSES, MAX_NAME_LENGTH};
+use crate::identification::Identification;
+use crate::keymap::{Action, Keymap, GAME_ACTIONS, MENU_ACTIONS};
+use crate::map::TileType;
+use crate::replay::Replay;
+use crate::{morgue, score};
 use crate::{
-    saveload_system, CombatStats, GameLog, InBackpack, Map, Name, Player, Position, RunState,
-    State, Viewshed,
+    player, saveload_system, AreaOfEffect, Attributes, CombatStats, Confusion, Description,
+    Equipped, GameLog, InBackpack, Map, Monster, Name, Player, Position, Price, Rarity, RunState,
+    State, Viewshed, Wallet, Weight,
 };
 use rltk::{Point, Rltk, VirtualKeyCode, RGB};
 use specs::prelude::*;
@@ -39,7 +46,7 @@ pub fn draw_ui(ecs: &World, ctx: &mut Rltk) {
         ctx.draw_bar_horizontal(
             28,
             43,
-            51,
+            36,
             stats.hp,
             stats.max_hp,
             RGB::named(rltk::RED),
@@ -47,13 +54,20 @@ pub fn draw_ui(ecs: &World, ctx: &mut Rltk) {
         );
     }
 
+    let wallets = ecs.read_storage::<Wallet>();
+    for (_player, wallet) in (&players, &wallets).join() {
+        ctx.print_color(
+            66,
+            43,
+            RGB::named(rltk::GOLD),
+            RGB::named(rltk::BLACK),
+            format!("Gold: {}", wallet.gold),
+        );
+    }
+
     let log = ecs.fetch::<GameLog>();
-    let mut y = 44;
-    for s in log.entries.iter().rev() {
-        if y < 49 {
-            ctx.print(2, y, s);
-        }
-        y += 1;
+    for (y, s) in (44..49).zip(log.entries.iter().rev()) {
+        ctx.print(2, y, s);
     }
 
     let mouse_pos = ctx.mouse_pos();
@@ -71,11 +85,12 @@ fn draw_tooltips(ecs: &World, ctx: &mut Rltk) {
     if mouse_pos.0 >= map.width || mouse_pos.1 >= map.height {
         return;
     }
+    let identification = ecs.fetch::<Identification>();
     let mut tooltip: Vec<String> = Vec::new();
     for (name, position) in (&names, &positions).join() {
         let idx = map.xy_idx(position.x, position.y);
         if position.x == mouse_pos.0 && position.y == mouse_pos.1 && map.visible_tiles[idx] {
-            tooltip.push(name.name.to_string());
+            tooltip.push(identification.display_name(&name.name));
         }
     }
 
@@ -91,8 +106,7 @@ fn draw_tooltips(ecs: &World, ctx: &mut Rltk) {
         if mouse_pos.0 > 40 {
             let arrow_pos = Point::new(mouse_pos.0 - 2, mouse_pos.1);
             let left_x = mouse_pos.0 - width;
-            let mut y = mouse_pos.1;
-            for s in tooltip.iter() {
+            for (y, s) in (mouse_pos.1..).zip(tooltip.iter()) {
                 ctx.print_color(
                     left_x,
                     y,
@@ -107,23 +121,21 @@ fn draw_tooltips(ecs: &World, ctx: &mut Rltk) {
                         y,
                         RGB::named(rltk::WHITE),
                         RGB::named(rltk::GREY),
-                        &" ".to_string(),
+                        " ",
                     );
                 }
-                y += 1;
             }
             ctx.print_color(
                 arrow_pos.x,
                 arrow_pos.y,
                 RGB::named(rltk::WHITE),
                 RGB::named(rltk::GREY),
-                &"->".to_string(),
+                "->",
             );
         } else {
             let arrow_pos = Point::new(mouse_pos.0 + 1, mouse_pos.1);
             let left_x = mouse_pos.0 + 3;
-            let mut y = mouse_pos.1;
-            for s in tooltip.iter() {
+            for (y, s) in (mouse_pos.1..).zip(tooltip.iter()) {
                 ctx.print_color(
                     left_x + 1,
                     y,
@@ -138,17 +150,16 @@ fn draw_tooltips(ecs: &World, ctx: &mut Rltk) {
                         y,
                         RGB::named(rltk::WHITE),
                         RGB::named(rltk::GREY),
-                        &" ".to_string(),
+                        " ",
                     );
                 }
-                y += 1;
             }
             ctx.print_color(
                 arrow_pos.x,
                 arrow_pos.y,
                 RGB::named(rltk::WHITE),
                 RGB::named(rltk::GREY),
-                &" ".to_string(),
+                " ",
             );
         }
     }
@@ -161,17 +172,114 @@ pub enum ItemMenuResult {
     Selected,
 }
 
-pub fn show_inventory(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option<Entity>) {
+const ITEMS_PER_PAGE: usize = 26;
+
+/// The items `owner` is carrying, grouped into stacks of identically named
+/// items and listed in the order they were first picked up.
+pub fn backpack_stacks(ecs: &World, owner: Entity) -> Vec<(String, Vec<Entity>)> {
+    let entities = ecs.entities();
+    let names = ecs.read_storage::<Name>();
+    let backpack = ecs.read_storage::<InBackpack>();
+
+    let mut stacks: Vec<(i32, String, Vec<Entity>)> = Vec::new();
+    for (entity, pack, name) in (&entities, &backpack, &names).join() {
+        if pack.owner != owner {
+            continue;
+        }
+        match stacks
+            .iter_mut()
+            .find(|(order, stack_name, _)| *order == pack.order && *stack_name == name.name)
+        {
+            Some(stack) => stack.2.push(entity),
+            None => stacks.push((pack.order, name.name.clone(), vec![entity])),
+        }
+    }
+    stacks.sort_by_key(|stack| stack.0);
+
+    stacks
+        .into_iter()
+        .map(|(_, name, items)| (name, items))
+        .collect()
+}
+
+pub fn show_inventory(
+    gs: &mut State,
+    ctx: &mut Rltk,
+    page: &mut usize,
+) -> (ItemMenuResult, Option<Entity>) {
+    let player_entity = *gs.ecs.fetch::<Entity>();
+    item_menu(gs, ctx, "Inventory", player_entity, None, page)
+}
+
+pub fn drop_item_menu(
+    gs: &mut State,
+    ctx: &mut Rltk,
+    page: &mut usize,
+) -> (ItemMenuResult, Option<Entity>) {
+    let player_entity = *gs.ecs.fetch::<Entity>();
+    item_menu(gs, ctx, "Drop what?", player_entity, None, page)
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum ShopMode {
+    Buy,
+    Sell,
+}
+
+/// Lists the vendor's wares, or the player's own items when selling, with
+/// what each would cost or fetch.
+pub fn shop_menu(
+    gs: &mut State,
+    ctx: &mut Rltk,
+    vendor: Entity,
+    mode: &mut ShopMode,
+    page: &mut usize,
+) -> (ItemMenuResult, Option<Entity>) {
+    let switch = ctx.key.is_some_and(|key| {
+        gs.ecs
+            .fetch::<Keymap>()
+            .action_for(key, &[Action::MenuSwitchMode])
+            .is_some()
+    });
+    if switch {
+        *mode = match mode {
+            ShopMode::Buy => ShopMode::Sell,
+            ShopMode::Sell => ShopMode::Buy,
+        };
+        *page = 0;
+        return (ItemMenuResult::NoResponse, None);
+    }
+
+    let (title, owner) = match mode {
+        ShopMode::Buy => ("Buy what?", vendor),
+        ShopMode::Sell => ("Sell what?", *gs.ecs.fetch::<Entity>()),
+    };
+    item_menu(gs, ctx, title, owner, Some(*mode), page)
+}
+
+fn item_menu(
+    gs: &mut State,
+    ctx: &mut Rltk,
+    title: &str,
+    owner: Entity,
+    shop: Option<ShopMode>,
+    page: &mut usize,
+) -> (ItemMenuResult, Option<Entity>) {
     let player_entity = gs.ecs.fetch::<Entity>();
-    let names = gs.ecs.read_storage::<Name>();
-    let backpack = gs.ecs.read_storage::<InBackpack>();
+    let keymap = gs.ecs.fetch::<Keymap>();
+    let identification = gs.ecs.fetch::<Identification>();
+    let rarities = gs.ecs.read_storage::<Rarity>();
+    let equipped = gs.ecs.read_storage::<Equipped>();
+    let prices = gs.ecs.read_storage::<Price>();
 
-    let inventory = (&backpack, &names)
-        .join()
-        .filter(|item| item.0.owner == *player_entity);
-    let count = inventory.count();
+    let stacks = backpack_stacks(&gs.ecs, owner);
+    let page_count = usize::max(1, stacks.len().div_ceil(ITEMS_PER_PAGE));
+    *page = usize::min(*page, page_count - 1);
+    let shown = &stacks[usize::min(*page * ITEMS_PER_PAGE, stacks.len())
+        ..usize::min((*page + 1) * ITEMS_PER_PAGE, stacks.len())];
+    let count = shown.len();
 
-    let mut y = (25 - (count / 2)) as i32;
+    let y = (25 - (count / 2)) as i32;
     ctx.draw_box(
         15,
         y - 2,
@@ -185,8 +293,17 @@ pub fn show_inventory(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option
         y - 2,
         RGB::named(rltk::YELLOW),
         RGB::named(rltk::BLACK),
-        "Inventory",
+        title,
     );
+    if page_count > 1 {
+        ctx.print_color(
+            36,
+            y - 2,
+            RGB::named(rltk::YELLOW),
+            RGB::named(rltk::BLACK),
+            format!("{}/{}", *page + 1, page_count),
+        );
+    }
     ctx.print_color(
         18,
         y + count as i32 + 1,
@@ -194,15 +311,25 @@ pub fn show_inventory(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option
         RGB::named(rltk::BLACK),
         "ESCAPE to cancel",
     );
+    if let Some(attr) = gs.ecs.read_storage::<Attributes>().get(*player_entity) {
+        let weights = gs.ecs.read_storage::<Weight>();
+        let carried: i32 = backpack_stacks(&gs.ecs, *player_entity)
+            .iter()
+            .flat_map(|(_, items)| items.iter())
+            .filter_map(|item| weights.get(*item))
+            .map(|weight| weight.weight)
+            .sum();
+        ctx.print_color(
+            35,
+            y + count as i32 + 1,
+            RGB::named(rltk::YELLOW),
+            RGB::named(rltk::BLACK),
+            format!("{}/{} lb", carried, attr.carry_capacity()),
+        );
+    }
 
-    let entities = gs.ecs.entities();
-    let mut equippable: Vec<Entity> = Vec::new();
-
-    for (j, (entity, _pack, name)) in (&entities, &backpack, &names)
-        .join()
-        .filter(|item| item.1.owner == *player_entity)
-        .enumerate()
-    {
+    for (j, (name, items)) in shown.iter().enumerate() {
+        let y = y + j as i32;
         ctx.set(
             17,
             y,
@@ -225,38 +352,79 @@ pub fn show_inventory(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option
             rltk::to_cp437(')'),
         );
 
-        ctx.print(21, y, &name.name.to_string());
-        equippable.push(entity);
-        y += 1;
+        let mut name = identification.display_name(name);
+        if items.len() > 1 {
+            name = format!("{} ({})", name, items.len());
+        }
+        if equipped.get(items[0]).is_some() {
+            name = format!("{} (worn)", name);
+        }
+        let colour = rarities
+            .get(items[0])
+            .map_or(RGB::named(rltk::WHITE), |rarity| {
+                rarity.apparent_tier().colour()
+            });
+        ctx.print_color(21, y, colour, RGB::named(rltk::BLACK), name);
+
+        if let Some(mode) = shop {
+            let price = prices.get(items[0]).map(|price| match mode {
+                ShopMode::Buy => price.value,
+                ShopMode::Sell => price.sale_value(),
+            });
+            if let Some(price) = price {
+                ctx.print_color(
+                    41,
+                    y,
+                    RGB::named(rltk::GOLD),
+                    RGB::named(rltk::BLACK),
+                    format!("{:>4}g", price),
+                );
+            }
+        }
     }
 
     match ctx.key {
         None => (ItemMenuResult::NoResponse, None),
-        Some(VirtualKeyCode::Escape) => (ItemMenuResult::Cancel, None),
-        Some(key) => {
-            let selection = rltk::letter_to_option(key);
-            if selection > -1 && selection < count as i32 {
-                return (
-                    ItemMenuResult::Selected,
-                    Some(equippable[selection as usize]),
-                );
+        Some(key) => match keymap.action_for(key, MENU_ACTIONS) {
+            Some(Action::MenuCancel) => (ItemMenuResult::Cancel, None),
+            Some(Action::MenuNextPage) => {
+                *page = (*page + 1) % page_count;
+                (ItemMenuResult::NoResponse, None)
             }
-            (ItemMenuResult::NoResponse, None)
-        }
+            Some(Action::MenuPrevPage) => {
+                *page = (*page + page_count - 1) % page_count;
+                (ItemMenuResult::NoResponse, None)
+            }
+            _ => {
+                let selection = rltk::letter_to_option(key);
+                if selection > -1 && selection < count as i32 {
+                    return (
+                        ItemMenuResult::Selected,
+                        Some(shown[selection as usize].1[0]),
+                    );
+                }
+                (ItemMenuResult::NoResponse, None)
+            }
+        },
     }
 }
 
-pub fn drop_item_menu(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option<Entity>) {
-    let player_entity = gs.ecs.fetch::<Entity>();
+/// Lets the player tick off any of the items on their tile. `selected` holds
+/// one bit per listed item.
+pub fn pickup_menu(
+    gs: &mut State,
+    ctx: &mut Rltk,
+    selected: &mut u32,
+) -> (ItemMenuResult, Vec<Entity>) {
+    let keymap = gs.ecs.fetch::<Keymap>();
     let names = gs.ecs.read_storage::<Name>();
-    let backpack = gs.ecs.read_storage::<InBackpack>();
+    let identification = gs.ecs.fetch::<Identification>();
 
-    let inventory = (&backpack, &names)
-        .join()
-        .filter(|item| item.0.owner == *player_entity);
-    let count = inventory.count();
+    let mut items = player::items_underfoot(&gs.ecs);
+    items.truncate(ITEMS_PER_PAGE);
+    let count = items.len();
 
-    let mut y = (25 - (count / 2)) as i32;
+    let y = (25 - (count / 2)) as i32;
     ctx.draw_box(
         15,
         y - 2,
@@ -270,30 +438,25 @@ pub fn drop_item_menu(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option
         y - 2,
         RGB::named(rltk::YELLOW),
         RGB::named(rltk::BLACK),
-        "Drop what?",
+        "Pick up what?",
     );
     ctx.print_color(
         18,
         y + count as i32 + 1,
         RGB::named(rltk::YELLOW),
         RGB::named(rltk::BLACK),
-        "ESCAPE to cancel",
+        "ENTER to take, ESCAPE to cancel",
     );
 
-    let entities = gs.ecs.entities();
-    let mut equippable: Vec<Entity> = Vec::new();
-
-    for (j, (entity, _pack, name)) in (&entities, &backpack, &names)
-        .join()
-        .filter(|item| item.1.owner == *player_entity)
-        .enumerate()
-    {
+    for (j, item) in items.iter().enumerate() {
+        let y = y + j as i32;
+        let ticked = *selected & (1 << j) != 0;
         ctx.set(
             17,
             y,
             RGB::named(rltk::WHITE),
             RGB::named(rltk::BLACK),
-            rltk::to_cp437('('),
+            rltk::to_cp437(if ticked { '+' } else { '(' }),
         );
         ctx.set(
             18,
@@ -307,45 +470,83 @@ pub fn drop_item_menu(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option
             y,
             RGB::named(rltk::WHITE),
             RGB::named(rltk::BLACK),
-            rltk::to_cp437(')'),
+            rltk::to_cp437(if ticked { '+' } else { ')' }),
         );
-
-        ctx.print(21, y, &name.name.to_string());
-        equippable.push(entity);
-        y += 1;
+        if let Some(name) = names.get(*item) {
+            ctx.print(21, y, identification.display_name(&name.name));
+        }
     }
 
     match ctx.key {
-        None => (ItemMenuResult::NoResponse, None),
-        Some(VirtualKeyCode::Escape) => (ItemMenuResult::Cancel, None),
-        Some(key) => {
-            let selection = rltk::letter_to_option(key);
-            if selection > -1 && selection < count as i32 {
-                return (
-                    ItemMenuResult::Selected,
-                    Some(equippable[selection as usize]),
-                );
+        None => (ItemMenuResult::NoResponse, Vec::new()),
+        Some(key) => match keymap.action_for(key, MENU_ACTIONS) {
+            Some(Action::MenuCancel) => (ItemMenuResult::Cancel, Vec::new()),
+            Some(Action::MenuSelect) => {
+                let chosen: Vec<Entity> = items
+                    .iter()
+                    .enumerate()
+                    .filter(|(j, _)| *selected & (1 << j) != 0)
+                    .map(|(_, item)| *item)
+                    .collect();
+                if chosen.is_empty() {
+                    (ItemMenuResult::Cancel, chosen)
+                } else {
+                    (ItemMenuResult::Selected, chosen)
+                }
             }
-            (ItemMenuResult::NoResponse, None)
-        }
+            _ => {
+                let selection = rltk::letter_to_option(key);
+                if selection > -1 && selection < count as i32 {
+                    *selected ^= 1 << selection;
+                }
+                (ItemMenuResult::NoResponse, Vec::new())
+            }
+        },
     }
 }
 
+/// Visible monsters within `range` of the player, nearest first.
+pub fn targets_in_range(ecs: &World, range: i32) -> Vec<Point> {
+    let player_pos = ecs.fetch::<Point>();
+    let map = ecs.fetch::<Map>();
+    let monsters = ecs.read_storage::<Monster>();
+    let positions = ecs.read_storage::<Position>();
+
+    let mut targets: Vec<(f32, Point)> = (&monsters, &positions)
+        .join()
+        .filter(|(_, pos)| map.visible_tiles[map.xy_idx(pos.x, pos.y)])
+        .map(|(_, pos)| {
+            let target = Point::new(pos.x, pos.y);
+            let distance = rltk::DistanceAlg::Pythagoras.distance2d(*player_pos, target);
+            (distance, target)
+        })
+        .filter(|(distance, _)| *distance <= range as f32)
+        .collect();
+    targets.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+    targets.into_iter().map(|(_, target)| target).collect()
+}
+
 pub fn ranged_target(
     gs: &mut State,
     ctx: &mut Rltk,
     range: i32,
+    item: Entity,
+    cursor: &mut Point,
+    last_mouse: &mut Point,
 ) -> (ItemMenuResult, Option<Point>) {
     let player_entity = gs.ecs.fetch::<Entity>();
     let player_pos = gs.ecs.fetch::<Point>();
     let viewsheds = gs.ecs.read_storage::<Viewshed>();
+    let keymap = gs.ecs.fetch::<Keymap>();
+    let map = gs.ecs.fetch::<Map>();
 
     ctx.print_color(
         5,
         0,
         RGB::named(rltk::YELLOW),
         RGB::named(rltk::BLACK),
-        "Select Target:",
+        "Select Target: (TAB to cycle, ENTER to fire)",
     );
 
     let mut available_cells = Vec::new();
@@ -355,38 +556,597 @@ pub fn ranged_target(
             let distance = rltk::DistanceAlg::Pythagoras.distance2d(*player_pos, *idx);
             if distance <= range as f32 {
                 ctx.set_bg(idx.x, idx.y, RGB::named(rltk::BLUE));
-                available_cells.push(idx);
+                available_cells.push(*idx);
             }
         }
     } else {
         return (ItemMenuResult::Cancel, None);
     }
 
-    let mouse_pos = ctx.mouse_pos();
-    let mut valid_target = false;
-    for idx in available_cells.iter() {
-        if idx.x == mouse_pos.0 && idx.y == mouse_pos.1 {
-            valid_target = true;
-            break;
+    let mouse_pos = ctx.mouse_point();
+    if mouse_pos != *last_mouse {
+        *last_mouse = mouse_pos;
+        *cursor = mouse_pos;
+    }
+
+    if let Some(key) = ctx.key {
+        let direction = keymap
+            .action_for(key, GAME_ACTIONS)
+            .and_then(|action| action.direction());
+        if let Some((dx, dy)) = direction {
+            cursor.x = (cursor.x + dx).clamp(0, map.width - 1);
+            cursor.y = (cursor.y + dy).clamp(0, map.height - 1);
+        } else {
+            match keymap.action_for(key, MENU_ACTIONS) {
+                Some(Action::MenuCancel) => return (ItemMenuResult::Cancel, None),
+                Some(Action::MenuSelect) if available_cells.contains(cursor) => {
+                    return (ItemMenuResult::Selected, Some(*cursor));
+                }
+                Some(Action::NextTarget) => {
+                    let targets = targets_in_range(&gs.ecs, range);
+                    let next = match targets.iter().position(|t| t == cursor) {
+                        Some(current) => targets.get(current + 1).or_else(|| targets.first()),
+                        None => targets.first(),
+                    };
+                    if let Some(next) = next {
+                        *cursor = *next;
+                    }
+                }
+                _ => {}
+            }
         }
     }
+
+    let valid_target = available_cells.contains(cursor);
     if valid_target {
-        ctx.set_bg(mouse_pos.0, mouse_pos.1, RGB::named(rltk::CYAN));
-        if ctx.left_click {
-            return (
-                ItemMenuResult::Selected,
-                Some(Point::new(mouse_pos.0, mouse_pos.1)),
-            );
+        if let Some(area_effect) = gs.ecs.read_storage::<AreaOfEffect>().get(item) {
+            for tile in rltk::field_of_view(*cursor, area_effect.radius, &*map).iter() {
+                ctx.set_bg(tile.x, tile.y, RGB::named(rltk::ORANGE));
+            }
         }
+        ctx.set_bg(cursor.x, cursor.y, RGB::named(rltk::CYAN));
     } else {
-        ctx.set_bg(mouse_pos.0, mouse_pos.1, RGB::named(rltk::RED));
-        if ctx.left_click {
-            return (ItemMenuResult::Cancel, None);
+        ctx.set_bg(cursor.x, cursor.y, RGB::named(rltk::RED));
+    }
+
+    if ctx.left_click {
+        if available_cells.contains(&mouse_pos) {
+            return (ItemMenuResult::Selected, Some(mouse_pos));
         }
+        return (ItemMenuResult::Cancel, None);
     }
     (ItemMenuResult::NoResponse, None)
 }
 
+pub fn look_mode(gs: &mut State, ctx: &mut Rltk, cursor: &mut Point) -> ItemMenuResult {
+    {
+        let keymap = gs.ecs.fetch::<Keymap>();
+        let map = gs.ecs.fetch::<Map>();
+
+        if let Some(key) = ctx.key {
+            let direction = keymap
+                .action_for(key, GAME_ACTIONS)
+                .and_then(|action| action.direction());
+            if let Some((dx, dy)) = direction {
+                cursor.x = (cursor.x + dx).clamp(0, map.width - 1);
+                cursor.y = (cursor.y + dy).clamp(0, map.height - 1);
+            } else {
+                match keymap.action_for(key, MENU_ACTIONS) {
+                    Some(Action::MenuCancel) => return ItemMenuResult::Cancel,
+                    Some(Action::NextTarget) => {
+                        let targets = targets_in_range(&gs.ecs, map.width + map.height);
+                        let next = match targets.iter().position(|t| t == cursor) {
+                            Some(current) => targets.get(current + 1).or_else(|| targets.first()),
+                            None => targets.first(),
+                        };
+                        if let Some(next) = next {
+                            *cursor = *next;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    ctx.print_color(
+        5,
+        0,
+        RGB::named(rltk::YELLOW),
+        RGB::named(rltk::BLACK),
+        "Look: (TAB to cycle, ESCAPE to finish)",
+    );
+    ctx.set_bg(cursor.x, cursor.y, RGB::named(rltk::CYAN));
+
+    let lines = describe_tile(&gs.ecs, *cursor);
+    let left_x = if cursor.x > 40 { 1 } else { 47 };
+    ctx.draw_box(
+        left_x,
+        1,
+        31,
+        lines.len() as i32 + 1,
+        RGB::named(rltk::WHITE),
+        RGB::named(rltk::BLACK),
+    );
+    for (y, (colour, line)) in (2..).zip(lines.iter()) {
+        ctx.print_color(left_x + 1, y, *colour, RGB::named(rltk::BLACK), line);
+    }
+
+    ItemMenuResult::NoResponse
+}
+
+fn describe_tile(ecs: &World, target: Point) -> Vec<(RGB, String)> {
+    let map = ecs.fetch::<Map>();
+    let player_entity = ecs.fetch::<Entity>();
+    let names = ecs.read_storage::<Name>();
+    let descriptions = ecs.read_storage::<Description>();
+    let combat_stats = ecs.read_storage::<CombatStats>();
+    let confusion = ecs.read_storage::<Confusion>();
+    let monsters = ecs.read_storage::<Monster>();
+    let identification = ecs.fetch::<Identification>();
+
+    let mut lines = Vec::new();
+    let idx = map.xy_idx(target.x, target.y);
+    if !map.revealed_tiles[idx] {
+        lines.push((RGB::named(rltk::GREY), "Unexplored".to_string()));
+        return lines;
+    }
+
+    let tile_name = match map.tiles[idx] {
+        TileType::Floor => "Floor",
+        TileType::Wall => "Wall",
+        TileType::DownStairs => "Stairs leading down",
+    };
+    if map.visible_tiles[idx] {
+        lines.push((RGB::named(rltk::WHITE), tile_name.to_string()));
+    } else {
+        lines.push((
+            RGB::named(rltk::GREY),
+            format!("{} (remembered)", tile_name),
+        ));
+        return lines;
+    }
+
+    for entity in map.tile_content[idx].iter() {
+        let name = match names.get(*entity) {
+            Some(name) => name,
+            None => continue,
+        };
+        lines.push((RGB::named(rltk::BLACK), String::new()));
+        lines.push((
+            RGB::named(rltk::YELLOW),
+            identification.display_name(&name.name),
+        ));
+        if !identification.is_identified(&name.name) {
+            lines.push((
+                RGB::named(rltk::WHITE),
+                "You don't know what this does.".to_string(),
+            ));
+        } else if let Some(description) = descriptions.get(*entity) {
+            for line in wrap_text(&description.description, 29) {
+                lines.push((RGB::named(rltk::WHITE), line));
+            }
+        }
+        if let Some(stats) = combat_stats.get(*entity) {
+            lines.push((
+                RGB::named(rltk::WHITE),
+                format!(
+                    "HP {}/{}  Power {}  Defense {}",
+                    stats.hp, stats.max_hp, stats.power, stats.defense
+                ),
+            ));
+            if let (Some(_), Some(player_stats)) =
+                (monsters.get(*entity), combat_stats.get(*player_entity))
+            {
+                let (threat, colour) = threat_estimate(player_stats, stats);
+                lines.push((colour, format!("Threat: {}", threat)));
+            }
+        }
+        if let Some(confused) = confusion.get(*entity) {
+            if monsters.get(*entity).is_some() {
+                lines.push((
+                    RGB::named(rltk::MAGENTA),
+                    format!("Confused ({} turns)", confused.turns),
+                ));
+            }
+        }
+    }
+
+    lines
+}
+
+/// A rough guess at how a melee fight between the player and `monster`
+/// would go, comparing how many turns each side needs to win.
+fn threat_estimate(player: &CombatStats, monster: &CombatStats) -> (&'static str, RGB) {
+    let their_damage = i32::max(0, monster.power - player.defense);
+    if their_damage == 0 {
+        return ("Harmless", RGB::named(rltk::GREEN));
+    }
+    let turns_to_lose = (player.hp + their_damage - 1) / their_damage;
+
+    let our_damage = i32::max(0, player.power - monster.defense);
+    if our_damage == 0 {
+        return ("Deadly", RGB::named(rltk::RED));
+    }
+    let turns_to_win = (monster.hp + our_damage - 1) / our_damage;
+
+    if turns_to_win * 3 <= turns_to_lose {
+        ("Trivial", RGB::named(rltk::GREEN))
+    } else if turns_to_win < turns_to_lose {
+        ("Manageable", RGB::named(rltk::YELLOW))
+    } else if turns_to_lose > 2 {
+        ("Dangerous", RGB::named(rltk::ORANGE))
+    } else {
+        ("Deadly", RGB::named(rltk::RED))
+    }
+}
+
+fn wrap_text(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        if !line.is_empty() && line.len() + word.len() + 1 > width {
+            lines.push(line);
+            line = String::new();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+pub fn show_help(gs: &mut State, ctx: &mut Rltk) -> ItemMenuResult {
+    let keymap = gs.ecs.fetch::<Keymap>();
+    let count = keymap.bindings.len() as i32;
+
+    let y = 25 - (count / 2);
+    ctx.draw_box(
+        10,
+        y - 2,
+        59,
+        count + 3,
+        RGB::named(rltk::WHITE),
+        RGB::named(rltk::BLACK),
+    );
+    ctx.print_color(
+        13,
+        y - 2,
+        RGB::named(rltk::YELLOW),
+        RGB::named(rltk::BLACK),
+        "Key Bindings",
+    );
+    ctx.print_color(
+        13,
+        y + count + 1,
+        RGB::named(rltk::YELLOW),
+        RGB::named(rltk::BLACK),
+        "Press any key to continue",
+    );
+
+    for (row, action) in (y..).zip(keymap.bindings.keys()) {
+        ctx.print_color(
+            12,
+            row,
+            RGB::named(rltk::YELLOW),
+            RGB::named(rltk::BLACK),
+            action.description(),
+        );
+        ctx.print(36, row, keymap.keys_for(*action));
+    }
+
+    match ctx.key {
+        None => ItemMenuResult::NoResponse,
+        Some(_) => ItemMenuResult::Cancel,
+    }
+}
+
+pub fn victory_screen(ecs: &World, ctx: &mut Rltk) -> ItemMenuResult {
+    let entry = score::final_score(ecs, true);
+
+    ctx.print_color_centered(
+        15,
+        RGB::named(rltk::MAGENTA),
+        RGB::named(rltk::BLACK),
+        "You have won!",
+    );
+    ctx.print_color_centered(
+        17,
+        RGB::named(rltk::WHITE),
+        RGB::named(rltk::BLACK),
+        "The Iron Crown is yours, and the dungeon with it.",
+    );
+    let lines = [
+        format!("Turns taken: {}", entry.turns),
+        format!("Monsters slain: {}", entry.kills),
+        format!("Gold carried: {}", entry.gold),
+        format!("Final score: {}", entry.score),
+    ];
+    for (row, line) in (20..).zip(lines.iter()) {
+        ctx.print_color_centered(row, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), line);
+    }
+    ctx.print_color_centered(
+        26,
+        RGB::named(rltk::GREY),
+        RGB::named(rltk::BLACK),
+        "Press any key to return to the menu",
+    );
+
+    match ctx.key {
+        None => ItemMenuResult::NoResponse,
+        Some(_) => ItemMenuResult::Selected,
+    }
+}
+
+pub fn game_over(ecs: &World, ctx: &mut Rltk) -> ItemMenuResult {
+    let entry = score::final_score(ecs, false);
+
+    ctx.print_color_centered(
+        15,
+        RGB::named(rltk::RED),
+        RGB::named(rltk::BLACK),
+        "Your journey has ended!",
+    );
+    ctx.print_color_centered(
+        17,
+        RGB::named(rltk::WHITE),
+        RGB::named(rltk::BLACK),
+        &entry.cause,
+    );
+    let lines = [
+        format!("Reached depth: {}", entry.depth),
+        format!("Turns taken: {}", entry.turns),
+        format!("Monsters slain: {}", entry.kills),
+        format!("Final score: {}", entry.score),
+    ];
+    for (row, line) in (20..).zip(lines.iter()) {
+        ctx.print_color_centered(row, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), line);
+    }
+    ctx.print_color_centered(
+        25,
+        RGB::named(rltk::GREY),
+        RGB::named(rltk::BLACK),
+        format!("A full account is in {}", morgue::morgue_file(entry.seed)),
+    );
+    ctx.print_color_centered(
+        27,
+        RGB::named(rltk::GREY),
+        RGB::named(rltk::BLACK),
+        "Press any key to return to the menu",
+    );
+
+    match ctx.key {
+        None => ItemMenuResult::NoResponse,
+        Some(_) => ItemMenuResult::Selected,
+    }
+}
+
+/// The best finished runs, listed under the main menu.
+fn draw_high_scores(ecs: &World, ctx: &mut Rltk) {
+    let scores = &ecs.fetch::<score::HighScores>().entries;
+    if scores.is_empty() {
+        return;
+    }
+
+    ctx.print_color_centered(
+        30,
+        RGB::named(rltk::YELLOW),
+        RGB::named(rltk::BLACK),
+        "High Scores",
+    );
+    ctx.print_color(
+        4,
+        32,
+        RGB::named(rltk::GREY),
+        RGB::named(rltk::BLACK),
+        "    Name          Depth Turns Kills Score  Fate",
+    );
+    for (row, (rank, entry)) in (33..).zip(scores.iter().enumerate()) {
+        let colour = if entry.won {
+            RGB::named(rltk::MAGENTA)
+        } else {
+            RGB::named(rltk::WHITE)
+        };
+        let line = format!(
+            "{:>2}. {:<13} {:>5} {:>5} {:>5} {:>5}  {}",
+            rank + 1,
+            entry.name.chars().take(13).collect::<String>(),
+            entry.depth,
+            entry.turns,
+            entry.kills,
+            entry.score,
+            entry.cause.chars().take(26).collect::<String>()
+        );
+        ctx.print_color(4, row, colour, RGB::named(rltk::BLACK), &line);
+    }
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum CreationStep {
+    Name,
+    Class,
+    Attributes,
+}
+
+/// The letter, digit or punctuation a key types into a name, if any.
+fn typed_char(key: VirtualKeyCode, shift: bool) -> Option<char> {
+    let letter = rltk::letter_to_option(key);
+    if letter >= 0 {
+        let c = (b'a' + letter as u8) as char;
+        return Some(if shift { c.to_ascii_uppercase() } else { c });
+    }
+    match key {
+        VirtualKeyCode::Space => Some(' '),
+        VirtualKeyCode::Minus => Some('-'),
+        VirtualKeyCode::Apostrophe => Some('\''),
+        _ => None,
+    }
+}
+
+/// Walks the player through naming their character, picking a class and
+/// optionally rolling attributes. The choices go straight into the replay's
+/// character sheet; `Selected` means they're done, `Cancel` that they backed
+/// out to the main menu.
+pub fn character_creation(
+    gs: &mut State,
+    ctx: &mut Rltk,
+    step: &mut CreationStep,
+) -> ItemMenuResult {
+    let keymap = gs.ecs.fetch::<Keymap>();
+    let mut replay = gs.ecs.fetch_mut::<Replay>();
+    let sheet = &mut replay.character;
+
+    ctx.print_color_centered(
+        10,
+        RGB::named(rltk::YELLOW),
+        RGB::named(rltk::BLACK),
+        "Create Your Character",
+    );
+    let action = ctx.key.and_then(|key| {
+        keymap
+            .action_for(key, &[Action::RollAttributes])
+            .filter(|_| *step == CreationStep::Attributes)
+            .or_else(|| keymap.action_for(key, MENU_ACTIONS))
+    });
+
+    match *step {
+        CreationStep::Name => {
+            ctx.print_color_centered(
+                14,
+                RGB::named(rltk::WHITE),
+                RGB::named(rltk::BLACK),
+                "What is your name?",
+            );
+            ctx.print_color_centered(
+                16,
+                RGB::named(rltk::MAGENTA),
+                RGB::named(rltk::BLACK),
+                format!("{}_", sheet.name),
+            );
+            ctx.print_color_centered(
+                19,
+                RGB::named(rltk::GREY),
+                RGB::named(rltk::BLACK),
+                "Type a name and press Enter, or Escape to go back",
+            );
+
+            match (action, ctx.key) {
+                (Some(Action::MenuCancel), _) => return ItemMenuResult::Cancel,
+                (Some(Action::MenuSelect), _) => {
+                    let trimmed = sheet.name.trim().to_string();
+                    sheet.name = if trimmed.is_empty() {
+                        CharacterSheet::default().name
+                    } else {
+                        trimmed
+                    };
+                    *step = CreationStep::Class;
+                }
+                (_, Some(VirtualKeyCode::Back)) => {
+                    sheet.name.pop();
+                }
+                (_, Some(key)) => {
+                    if let Some(c) = typed_char(key, ctx.shift) {
+                        if sheet.name.chars().count() < MAX_NAME_LENGTH {
+                            sheet.name.push(c);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        CreationStep::Class => {
+            ctx.print_color_centered(
+                14,
+                RGB::named(rltk::WHITE),
+                RGB::named(rltk::BLACK),
+                format!("Choose a class for {}:", sheet.name),
+            );
+            for (row, class) in (17..).step_by(3).zip(CLASSES.iter()) {
+                let colour = if *class == sheet.class {
+                    RGB::named(rltk::MAGENTA)
+                } else {
+                    RGB::named(rltk::WHITE)
+                };
+                ctx.print_color_centered(row, colour, RGB::named(rltk::BLACK), class.name());
+                ctx.print_color_centered(
+                    row + 1,
+                    RGB::named(rltk::GREY),
+                    RGB::named(rltk::BLACK),
+                    class.description(),
+                );
+            }
+
+            let current = CLASSES.iter().position(|c| *c == sheet.class).unwrap_or(0);
+            match action {
+                Some(Action::MenuCancel) => *step = CreationStep::Name,
+                Some(Action::MenuSelect) => *step = CreationStep::Attributes,
+                Some(Action::MenuUp) => {
+                    sheet.class = CLASSES[(current + CLASSES.len() - 1) % CLASSES.len()];
+                }
+                Some(Action::MenuDown) => {
+                    sheet.class = CLASSES[(current + 1) % CLASSES.len()];
+                }
+                _ => {}
+            }
+        }
+        CreationStep::Attributes => {
+            ctx.print_color_centered(
+                14,
+                RGB::named(rltk::WHITE),
+                RGB::named(rltk::BLACK),
+                format!("{} the {}", sheet.name, sheet.class.name()),
+            );
+            let strength = Attributes {
+                strength: sheet.strength,
+            };
+            let lines = [
+                format!(
+                    "Strength: {:>2}  (carries up to {})",
+                    sheet.strength,
+                    strength.carry_capacity()
+                ),
+                format!(
+                    "Toughness: {:>2}  ({:+} hp)",
+                    sheet.toughness,
+                    sheet.hp_bonus()
+                ),
+            ];
+            for (row, line) in (17..).zip(lines.iter()) {
+                ctx.print_color_centered(
+                    row,
+                    RGB::named(rltk::YELLOW),
+                    RGB::named(rltk::BLACK),
+                    line,
+                );
+            }
+            ctx.print_color_centered(
+                21,
+                RGB::named(rltk::GREY),
+                RGB::named(rltk::BLACK),
+                format!(
+                    "{} to roll attributes, Enter to begin, Escape to go back",
+                    keymap.keys_for(Action::RollAttributes)
+                ),
+            );
+
+            match action {
+                Some(Action::MenuCancel) => *step = CreationStep::Class,
+                Some(Action::MenuSelect) => return ItemMenuResult::Selected,
+                Some(Action::RollAttributes) => {
+                    sheet.roll_attributes(&mut rltk::RandomNumberGenerator::new())
+                }
+                _ => {}
+            }
+        }
+    }
+
+    ItemMenuResult::NoResponse
+}
+
 #[derive(PartialEq, Copy, Clone)]
 pub enum MainMenuSelection {
     NewGame,
@@ -460,15 +1220,21 @@ pub fn main_menu(gs: &mut State, ctx: &mut Rltk) -> MainMenuResult {
             ctx.print_color_centered(26, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), "Quit");
         }
 
+        if gs.ecs.try_fetch::<saveload_system::LoadFailed>().is_some() {
+            ctx.print_color_centered(
+                28,
+                RGB::named(rltk::RED),
+                RGB::named(rltk::BLACK),
+                "The saved game could not be loaded.",
+            );
+        }
+
+        draw_high_scores(&gs.ecs, ctx);
+
         if let Some(key) = ctx.key {
-            use VirtualKeyCode::*;
-            match key {
-                Escape => {
-                    return MainMenuResult::NoSelection {
-                        selected: selection,
-                    }
-                }
-                Up => {
+            let keymap = gs.ecs.fetch::<Keymap>();
+            match keymap.action_for(key, MENU_ACTIONS) {
+                Some(Action::MenuUp) => {
                     return MainMenuResult::NoSelection {
                         selected: match selection {
                             MainMenuSelection::NewGame => MainMenuSelection::Quit,
@@ -477,7 +1243,7 @@ pub fn main_menu(gs: &mut State, ctx: &mut Rltk) -> MainMenuResult {
                         },
                     }
                 }
-                Down => {
+                Some(Action::MenuDown) => {
                     return MainMenuResult::NoSelection {
                         selected: match selection {
                             MainMenuSelection::NewGame => MainMenuSelection::LoadGame,
@@ -486,7 +1252,7 @@ pub fn main_menu(gs: &mut State, ctx: &mut Rltk) -> MainMenuResult {
                         },
                     }
                 }
-                Return => {
+                Some(Action::MenuSelect) => {
                     return MainMenuResult::Selected {
                         selected: selection,
                     };
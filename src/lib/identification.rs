@@ -0,0 +1,72 @@
+use rltk::RandomNumberGenerator;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+pub const POTIONS: &[&str] = &["Health Potion"];
+pub const SCROLLS: &[&str] = &[
+    "Magic Missile Scrolle",
+    "Fireball Scroll",
+    "Confusion Scroll",
+    "Remove Curse Scroll",
+];
+
+const POTION_LOOKS: &[&str] = &[
+    "murky", "bubbling", "cloudy", "fizzy", "oily", "smoky", "viscous", "glowing", "swirling",
+    "milky",
+];
+const SCROLL_SYLLABLES: &[&str] = &[
+    "xy", "zzy", "ka", "bor", "eth", "nu", "plo", "qua", "ri", "zan", "vel", "om",
+];
+
+/// Which scroll and potion types the player has learned this run, and the
+/// made-up names shown for the ones they haven't.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Identification {
+    pub unidentified_names: HashMap<String, String>,
+    pub identified: HashSet<String>,
+}
+
+impl Identification {
+    pub fn new_game(rng: &mut RandomNumberGenerator) -> Identification {
+        let mut unidentified_names = HashMap::new();
+
+        let mut looks: Vec<&str> = POTION_LOOKS.to_vec();
+        for potion in POTIONS.iter() {
+            let look = looks.remove(rng.range(0, looks.len()));
+            unidentified_names.insert(potion.to_string(), format!("{} potion", look));
+        }
+
+        let mut used: HashSet<String> = HashSet::new();
+        for scroll in SCROLLS.iter() {
+            let mut label = String::new();
+            while label.is_empty() || used.contains(&label) {
+                label = (0..rng.range(2, 4))
+                    .map(|_| *rng.random_slice_entry(SCROLL_SYLLABLES).unwrap())
+                    .collect::<String>()
+                    .to_uppercase();
+            }
+            used.insert(label.clone());
+            unidentified_names.insert(scroll.to_string(), format!("scroll of {}", label));
+        }
+
+        Identification {
+            unidentified_names,
+            identified: HashSet::new(),
+        }
+    }
+
+    pub fn display_name(&self, name: &str) -> String {
+        match self.unidentified_names.get(name) {
+            Some(unidentified) if !self.identified.contains(name) => unidentified.clone(),
+            _ => name.to_string(),
+        }
+    }
+
+    pub fn is_identified(&self, name: &str) -> bool {
+        !self.unidentified_names.contains_key(name) || self.identified.contains(name)
+    }
+
+    pub fn identify(&mut self, name: &str) {
+        self.identified.insert(name.to_string());
+    }
+}
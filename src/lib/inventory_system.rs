@@ -1,44 +1,155 @@
 use crate::{
-    gamelog::GameLog, AreaOfEffect, CombatStats, Confusion, Consumable, InBackpack, InflictsDamage,
-    Map, Name, Position, ProvidesHealing, SufferDamage, WantsToDropItem, WantsToPickupItem,
-    WantsToUseItem,
+    gamelog::GameLog, identification::Identification, AreaOfEffect, Attributes, CombatStats,
+    Confusion, Consumable, Equippable, Equipped, Gold, InBackpack, InflictsDamage, Map, Name,
+    Position, ProvidesFood, ProvidesHealing, Rarity, RarityTier, RemovesCurse, SufferDamage,
+    Viewshed, Wallet, WantsToDropItem, WantsToPickupItem, WantsToUseItem, Weight,
 };
+use rltk::Point;
 use specs::prelude::*;
 
+/// Where a new item goes in a backpack: onto an existing stack of the same
+/// item and rarity, or into a new slot after everything already carried.
+/// Equipment never stacks, so each piece can be worn on its own.
+pub fn stack_order<'a>(
+    carried: impl Iterator<Item = (i32, &'a str, Option<RarityTier>)>,
+    name: &str,
+    tier: Option<RarityTier>,
+    stackable: bool,
+) -> i32 {
+    let mut next_order = 0;
+    for (order, carried_name, carried_tier) in carried {
+        if stackable && carried_name == name && carried_tier == tier {
+            return order;
+        }
+        next_order = i32::max(next_order, order + 1);
+    }
+    next_order
+}
+
+/// True if something standing at `pos` is in the player's sight, so that
+/// monsters going about their business out of view stay out of the log.
+fn seen_by_player(pos: Option<&Position>, player_view: Option<&Viewshed>) -> bool {
+    match (pos, player_view) {
+        (Some(pos), Some(view)) => view.visible_tiles.contains(&Point::new(pos.x, pos.y)),
+        _ => false,
+    }
+}
+
 pub struct ItemCollectionSystem {}
 
 type CollectionData<'a> = (
     ReadExpect<'a, Entity>,
+    Entities<'a>,
     WriteExpect<'a, GameLog>,
     WriteStorage<'a, WantsToPickupItem>,
     WriteStorage<'a, Position>,
     ReadStorage<'a, Name>,
     WriteStorage<'a, InBackpack>,
+    ReadStorage<'a, Weight>,
+    ReadStorage<'a, Attributes>,
+    ReadExpect<'a, Identification>,
+    ReadStorage<'a, Rarity>,
+    ReadStorage<'a, Equippable>,
+    ReadStorage<'a, Gold>,
+    WriteStorage<'a, Wallet>,
+    ReadStorage<'a, Viewshed>,
 );
 
 impl<'a> System<'a> for ItemCollectionSystem {
     type SystemData = CollectionData<'a>;
 
     fn run(&mut self, data: Self::SystemData) {
-        let (player_entity, mut gamelog, mut wants_pickup, mut positions, names, mut backpack) =
-            data;
+        let (
+            player_entity,
+            entities,
+            mut gamelog,
+            mut wants_pickup,
+            mut positions,
+            names,
+            mut backpack,
+            weights,
+            attributes,
+            identification,
+            rarities,
+            equippables,
+            gold,
+            mut wallets,
+            viewsheds,
+        ) = data;
 
         for pickup in wants_pickup.join() {
+            if let Some(coins) = gold.get(pickup.item) {
+                if let Some(wallet) = wallets.get_mut(pickup.collected_by) {
+                    wallet.gold += coins.amount;
+                    if pickup.collected_by == *player_entity {
+                        gamelog
+                            .entries
+                            .push(format!("You pick up {} gold.", coins.amount));
+                    }
+                    entities.delete(pickup.item).expect("Delete failed");
+                }
+                continue;
+            }
+
+            if let Some(attr) = attributes.get(pickup.collected_by) {
+                let carried: i32 = (&backpack, &weights)
+                    .join()
+                    .filter(|(pack, _)| pack.owner == pickup.collected_by)
+                    .map(|(_, weight)| weight.weight)
+                    .sum();
+                let item_weight = weights.get(pickup.item).map_or(0, |w| w.weight);
+                if carried + item_weight > attr.carry_capacity() {
+                    if pickup.collected_by == *player_entity {
+                        gamelog.entries.push(format!(
+                            "You can't carry the {}: it's too heavy.",
+                            identification.display_name(&names.get(pickup.item).unwrap().name)
+                        ));
+                    }
+                    continue;
+                }
+            }
+
             positions.remove(pickup.item);
+
+            let order = stack_order(
+                (&entities, &backpack, &names)
+                    .join()
+                    .filter(|(_, pack, _)| pack.owner == pickup.collected_by)
+                    .map(|(carried, pack, name)| {
+                        (
+                            pack.order,
+                            name.name.as_str(),
+                            rarities.get(carried).map(|r| r.tier),
+                        )
+                    }),
+                &names.get(pickup.item).unwrap().name,
+                rarities.get(pickup.item).map(|r| r.tier),
+                equippables.get(pickup.item).is_none(),
+            );
             backpack
                 .insert(
                     pickup.item,
                     InBackpack {
                         owner: pickup.collected_by,
+                        order,
                     },
                 )
                 .expect("Unable to insert backpack entry");
 
+            let item_name = identification.display_name(&names.get(pickup.item).unwrap().name);
             if pickup.collected_by == *player_entity {
-                gamelog.entries.push(format!(
-                    "You pick up the {}.",
-                    names.get(pickup.item).unwrap().name
-                ));
+                gamelog
+                    .entries
+                    .push(format!("You pick up the {}.", item_name));
+            } else if seen_by_player(
+                positions.get(pickup.collected_by),
+                viewsheds.get(*player_entity),
+            ) {
+                if let Some(collector) = names.get(pickup.collected_by) {
+                    gamelog
+                        .entries
+                        .push(format!("{} picks up the {}.", collector.name, item_name));
+                }
             }
         }
 
@@ -62,6 +173,14 @@ type ItemData<'a> = (
     WriteStorage<'a, SufferDamage>,
     ReadStorage<'a, AreaOfEffect>,
     WriteStorage<'a, Confusion>,
+    WriteExpect<'a, Identification>,
+    ReadStorage<'a, Equippable>,
+    WriteStorage<'a, Equipped>,
+    WriteStorage<'a, Rarity>,
+    ReadStorage<'a, RemovesCurse>,
+    ReadStorage<'a, ProvidesFood>,
+    ReadStorage<'a, Position>,
+    ReadStorage<'a, Viewshed>,
 );
 
 impl<'a> System<'a> for ItemUseSystem {
@@ -82,11 +201,112 @@ impl<'a> System<'a> for ItemUseSystem {
             mut suffer_damage,
             aoe,
             mut confused,
+            mut identification,
+            equippables,
+            mut equipped,
+            mut rarities,
+            removes_curse,
+            food,
+            positions,
+            viewsheds,
         ) = data;
 
-        let mut used_item = false;
-
         for (entity, useitem) in (&entities, &useitems).join() {
+            let mut used_item = false;
+            if entity == *player_entity {
+                if let Some(item_name) = names.get(useitem.item) {
+                    identification.identify(&item_name.name);
+                }
+            }
+
+            if let Some(equippable) = equippables.get(useitem.item) {
+                let is_cursed =
+                    |item: Entity| rarities.get(item).map(|r| r.tier) == Some(RarityTier::Cursed);
+                let item_name = &names.get(useitem.item).unwrap().name;
+
+                // Using worn equipment takes it off again.
+                if equipped.get(useitem.item).is_some() {
+                    if is_cursed(useitem.item) {
+                        if entity == *player_entity {
+                            gamelog
+                                .entries
+                                .push(format!("You can't remove the cursed {}!", item_name));
+                        }
+                    } else {
+                        equipped.remove(useitem.item);
+                        if entity == *player_entity {
+                            gamelog
+                                .entries
+                                .push(format!("You take off the {}.", item_name));
+                        }
+                    }
+                    continue;
+                }
+
+                let worn: Vec<Entity> = (&entities, &equipped)
+                    .join()
+                    .filter(|(_, worn)| worn.owner == entity && worn.slot == equippable.slot)
+                    .map(|(item, _)| item)
+                    .collect();
+                if let Some(stuck) = worn.iter().find(|item| is_cursed(**item)) {
+                    if entity == *player_entity {
+                        gamelog.entries.push(format!(
+                            "You can't remove the cursed {}!",
+                            names.get(*stuck).unwrap().name
+                        ));
+                    }
+                    continue;
+                }
+                for item in worn {
+                    equipped.remove(item);
+                }
+                equipped
+                    .insert(
+                        useitem.item,
+                        Equipped {
+                            owner: entity,
+                            slot: equippable.slot,
+                        },
+                    )
+                    .expect("Unable to insert equipped component");
+                if entity == *player_entity {
+                    gamelog
+                        .entries
+                        .push(format!("You equip the {}.", item_name));
+                    if is_cursed(useitem.item) {
+                        gamelog
+                            .entries
+                            .push("It's cursed! You can't take it off.".to_string());
+                    }
+                    if let Some(rarity) = rarities.get_mut(useitem.item) {
+                        rarity.disguise = None;
+                    }
+                }
+                continue;
+            }
+
+            if removes_curse.get(useitem.item).is_some() {
+                let owned: Vec<Entity> = (&entities, &equipped)
+                    .join()
+                    .filter(|(_, worn)| worn.owner == entity)
+                    .map(|(item, _)| item)
+                    .collect();
+                for item in owned {
+                    if let Some(rarity) = rarities.get_mut(item) {
+                        if rarity.tier == RarityTier::Cursed {
+                            rarity.tier = RarityTier::Common;
+                            rarity.disguise = None;
+                        }
+                    }
+                }
+                if entity == *player_entity {
+                    gamelog
+                        .entries
+                        .push("You feel as if someone is watching over you.".to_string());
+                }
+                used_item = true;
+            }
+
             let mut targets: Vec<Entity> = Vec::new();
 
             if let Some(target) = useitem.target {
@@ -105,23 +325,46 @@ impl<'a> System<'a> for ItemUseSystem {
                     }
                 }
             } else {
-                targets.push(*player_entity);
+                targets.push(entity);
             }
 
+            // Monsters use items too, so describe the user by name unless
+            // it's the player, and only if the player can see them.
+            let logged = entity == *player_entity
+                || seen_by_player(positions.get(entity), viewsheds.get(*player_entity));
+            let user_name = if entity == *player_entity {
+                "You".to_string()
+            } else {
+                names
+                    .get(entity)
+                    .map_or("Something".to_string(), |n| n.name.clone())
+            };
+            let verb = |you: &str, them: &str| {
+                if entity == *player_entity {
+                    you.to_string()
+                } else {
+                    them.to_string()
+                }
+            };
+            let item_name = identification.display_name(&names.get(useitem.item).unwrap().name);
+
             let mut add_confusion = Vec::new();
             {
                 if let Some(confusion) = confused.get(useitem.item) {
                     for mob in targets.iter() {
                         add_confusion.push((*mob, confusion.turns));
-                        if entity == *player_entity {
+                        if logged {
                             let mob_name = names.get(*mob).unwrap();
-                            let item_name = names.get(useitem.item).unwrap();
                             gamelog.entries.push(format!(
-                                "You use {} on {}, confusing them.",
-                                item_name.name, mob_name.name
+                                "{} {} {} on {}, confusing them.",
+                                user_name,
+                                verb("use", "uses"),
+                                item_name,
+                                mob_name.name
                             ));
                         }
                     }
+                    used_item = true;
                 }
             }
             for mob in add_confusion.iter() {
@@ -130,30 +373,62 @@ impl<'a> System<'a> for ItemUseSystem {
                     .expect("Unable to insert status");
             }
 
+            if let Some(food) = food.get(useitem.item) {
+                if let Some(stats) = combat_stats.get_mut(entity) {
+                    stats.hp = i32::min(stats.max_hp, stats.hp + food.nutrition);
+                }
+                if logged {
+                    gamelog.entries.push(format!(
+                        "{} {} the {}. It's filling, at least.",
+                        user_name,
+                        verb("eat", "eats"),
+                        item_name
+                    ));
+                }
+                used_item = true;
+            }
+
             if let Some(healer) = healing.get(useitem.item) {
                 for target in targets.iter() {
                     if let Some(stats) = combat_stats.get_mut(*target) {
                         stats.hp = i32::min(stats.max_hp, stats.hp + healer.heal_amount);
-                        if entity == *player_entity {
+                        if logged {
                             gamelog.entries.push(format!(
-                                "You drink the {}, healing {} hp",
-                                names.get(useitem.item).unwrap().name,
+                                "{} {} the {}, healing {} hp",
+                                user_name,
+                                verb("drink", "drinks"),
+                                item_name,
                                 healer.heal_amount
                             ));
                         }
                     }
                 }
+                used_item = true;
             }
 
             if let Some(damage) = inflicts_damage.get(useitem.item) {
+                let cause = if entity == *player_entity {
+                    format!("your own {}", item_name)
+                } else {
+                    format!("{}'s {}", user_name, item_name)
+                };
                 for mob in targets.iter() {
-                    SufferDamage::new_damage(&mut suffer_damage, *mob, damage.damage);
-                    if entity == *player_entity {
+                    SufferDamage::new_damage(
+                        &mut suffer_damage,
+                        *mob,
+                        damage.damage,
+                        cause.clone(),
+                        entity == *player_entity,
+                    );
+                    if logged {
                         let mob_name = names.get(*mob).unwrap();
-                        let item_name = names.get(useitem.item).unwrap();
                         gamelog.entries.push(format!(
-                            "You use {} on {}, inflicting {} hp.",
-                            item_name.name, mob_name.name, damage.damage
+                            "{} {} {} on {}, inflicting {} hp.",
+                            user_name,
+                            verb("use", "uses"),
+                            item_name,
+                            mob_name.name,
+                            damage.damage
                         ));
                     }
                     used_item = true;
@@ -178,6 +453,9 @@ type DropData<'a> = (
     ReadStorage<'a, Name>,
     WriteStorage<'a, Position>,
     WriteStorage<'a, InBackpack>,
+    ReadExpect<'a, Identification>,
+    WriteStorage<'a, Equipped>,
+    ReadStorage<'a, Rarity>,
 );
 
 impl<'a> System<'a> for ItemDropSystem {
@@ -192,9 +470,24 @@ impl<'a> System<'a> for ItemDropSystem {
             names,
             mut positions,
             mut backpack,
+            identification,
+            mut equipped,
+            rarities,
         ) = data;
 
         for (entity, to_drop) in (&entities, &wants_drop).join() {
+            let cursed = rarities.get(to_drop.item).map(|r| r.tier) == Some(RarityTier::Cursed);
+            if cursed && equipped.get(to_drop.item).is_some() {
+                if entity == *player_entity {
+                    gamelog.entries.push(format!(
+                        "You can't let go of the cursed {}!",
+                        names.get(to_drop.item).unwrap().name
+                    ));
+                }
+                continue;
+            }
+            equipped.remove(to_drop.item);
+
             let mut dropper_pos: Position = Position { x: 0, y: 0 };
             {
                 let dropped_pos = positions.get(entity).unwrap();
@@ -216,7 +509,7 @@ impl<'a> System<'a> for ItemDropSystem {
             if entity == *player_entity {
                 gamelog.entries.push(format!(
                     "You drop the {}",
-                    names.get(to_drop.item).unwrap().name
+                    identification.display_name(&names.get(to_drop.item).unwrap().name)
                 ));
             }
         }
@@ -224,3 +517,74 @@ impl<'a> System<'a> for ItemDropSystem {
         wants_drop.clear();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{register_components, Item};
+
+    #[test]
+    fn stack_order_puts_like_items_together() {
+        let carried = [
+            (0, "Health Potion", Some(RarityTier::Common)),
+            (3, "Dagger", Some(RarityTier::Common)),
+        ];
+        let order =
+            |name, tier, stackable| stack_order(carried.iter().copied(), name, tier, stackable);
+
+        assert_eq!(order("Health Potion", Some(RarityTier::Common), true), 0);
+        assert_eq!(order("Health Potion", Some(RarityTier::Magic), true), 4);
+        assert_eq!(order("Fireball Scroll", None, true), 4);
+        assert_eq!(order("Dagger", Some(RarityTier::Common), false), 4);
+        assert_eq!(stack_order(std::iter::empty(), "Dagger", None, true), 0);
+    }
+
+    fn potion(ecs: &mut World, tier: RarityTier) -> Entity {
+        ecs.create_entity()
+            .with(Item {})
+            .with(Position { x: 1, y: 1 })
+            .with(Name {
+                name: "Health Potion".to_string(),
+            })
+            .with(Rarity {
+                tier,
+                disguise: None,
+            })
+            .build()
+    }
+
+    #[test]
+    fn picked_up_items_join_their_stack() {
+        let mut ecs = World::new();
+        register_components(&mut ecs);
+        ecs.insert(GameLog {
+            entries: Vec::new(),
+        });
+        ecs.insert(Identification::default());
+        let player = ecs.create_entity().build();
+        ecs.insert(player);
+
+        let first = potion(&mut ecs, RarityTier::Common);
+        let second = potion(&mut ecs, RarityTier::Common);
+        let magic = potion(&mut ecs, RarityTier::Magic);
+        for item in [first, second, magic] {
+            ecs.write_storage::<WantsToPickupItem>()
+                .insert(
+                    item,
+                    WantsToPickupItem {
+                        collected_by: player,
+                        item,
+                    },
+                )
+                .unwrap();
+            ItemCollectionSystem {}.run_now(&ecs);
+        }
+
+        let backpack = ecs.read_storage::<InBackpack>();
+        let order = |item| backpack.get(item).unwrap().order;
+        assert_eq!(order(first), 0);
+        assert_eq!(order(second), 0);
+        assert_eq!(order(magic), 1);
+        assert!(ecs.read_storage::<Position>().get(second).is_none());
+    }
+}
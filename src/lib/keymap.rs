@@ -0,0 +1,393 @@
+use rltk::VirtualKeyCode;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::{fs::read_to_string, path::Path};
+
+#[cfg(not(target_arch = "wasm32"))]
+const KEYMAP_FILE: &str = "./keymap.json";
+
+/// Every key a binding can name, used to catch typos in `keymap.json`.
+#[cfg(not(target_arch = "wasm32"))]
+const KEY_CODES: &[VirtualKeyCode] = &[
+    VirtualKeyCode::Key1,
+    VirtualKeyCode::Key2,
+    VirtualKeyCode::Key3,
+    VirtualKeyCode::Key4,
+    VirtualKeyCode::Key5,
+    VirtualKeyCode::Key6,
+    VirtualKeyCode::Key7,
+    VirtualKeyCode::Key8,
+    VirtualKeyCode::Key9,
+    VirtualKeyCode::Key0,
+    VirtualKeyCode::A,
+    VirtualKeyCode::B,
+    VirtualKeyCode::C,
+    VirtualKeyCode::D,
+    VirtualKeyCode::E,
+    VirtualKeyCode::F,
+    VirtualKeyCode::G,
+    VirtualKeyCode::H,
+    VirtualKeyCode::I,
+    VirtualKeyCode::J,
+    VirtualKeyCode::K,
+    VirtualKeyCode::L,
+    VirtualKeyCode::M,
+    VirtualKeyCode::N,
+    VirtualKeyCode::O,
+    VirtualKeyCode::P,
+    VirtualKeyCode::Q,
+    VirtualKeyCode::R,
+    VirtualKeyCode::S,
+    VirtualKeyCode::T,
+    VirtualKeyCode::U,
+    VirtualKeyCode::V,
+    VirtualKeyCode::W,
+    VirtualKeyCode::X,
+    VirtualKeyCode::Y,
+    VirtualKeyCode::Z,
+    VirtualKeyCode::Escape,
+    VirtualKeyCode::F1,
+    VirtualKeyCode::F2,
+    VirtualKeyCode::F3,
+    VirtualKeyCode::F4,
+    VirtualKeyCode::F5,
+    VirtualKeyCode::F6,
+    VirtualKeyCode::F7,
+    VirtualKeyCode::F8,
+    VirtualKeyCode::F9,
+    VirtualKeyCode::F10,
+    VirtualKeyCode::F11,
+    VirtualKeyCode::F12,
+    VirtualKeyCode::F13,
+    VirtualKeyCode::F14,
+    VirtualKeyCode::F15,
+    VirtualKeyCode::F16,
+    VirtualKeyCode::F17,
+    VirtualKeyCode::F18,
+    VirtualKeyCode::F19,
+    VirtualKeyCode::F20,
+    VirtualKeyCode::F21,
+    VirtualKeyCode::F22,
+    VirtualKeyCode::F23,
+    VirtualKeyCode::F24,
+    VirtualKeyCode::Snapshot,
+    VirtualKeyCode::Scroll,
+    VirtualKeyCode::Pause,
+    VirtualKeyCode::Insert,
+    VirtualKeyCode::Home,
+    VirtualKeyCode::Delete,
+    VirtualKeyCode::End,
+    VirtualKeyCode::PageDown,
+    VirtualKeyCode::PageUp,
+    VirtualKeyCode::Left,
+    VirtualKeyCode::Up,
+    VirtualKeyCode::Right,
+    VirtualKeyCode::Down,
+    VirtualKeyCode::Back,
+    VirtualKeyCode::Return,
+    VirtualKeyCode::Space,
+    VirtualKeyCode::Compose,
+    VirtualKeyCode::Caret,
+    VirtualKeyCode::Numlock,
+    VirtualKeyCode::Numpad0,
+    VirtualKeyCode::Numpad1,
+    VirtualKeyCode::Numpad2,
+    VirtualKeyCode::Numpad3,
+    VirtualKeyCode::Numpad4,
+    VirtualKeyCode::Numpad5,
+    VirtualKeyCode::Numpad6,
+    VirtualKeyCode::Numpad7,
+    VirtualKeyCode::Numpad8,
+    VirtualKeyCode::Numpad9,
+    VirtualKeyCode::NumpadAdd,
+    VirtualKeyCode::NumpadDivide,
+    VirtualKeyCode::NumpadDecimal,
+    VirtualKeyCode::NumpadComma,
+    VirtualKeyCode::NumpadEnter,
+    VirtualKeyCode::NumpadEquals,
+    VirtualKeyCode::NumpadMultiply,
+    VirtualKeyCode::NumpadSubtract,
+    VirtualKeyCode::AbntC1,
+    VirtualKeyCode::AbntC2,
+    VirtualKeyCode::Apostrophe,
+    VirtualKeyCode::Apps,
+    VirtualKeyCode::Asterisk,
+    VirtualKeyCode::At,
+    VirtualKeyCode::Ax,
+    VirtualKeyCode::Backslash,
+    VirtualKeyCode::Calculator,
+    VirtualKeyCode::Capital,
+    VirtualKeyCode::Colon,
+    VirtualKeyCode::Comma,
+    VirtualKeyCode::Convert,
+    VirtualKeyCode::Equals,
+    VirtualKeyCode::Grave,
+    VirtualKeyCode::Kana,
+    VirtualKeyCode::Kanji,
+    VirtualKeyCode::LAlt,
+    VirtualKeyCode::LBracket,
+    VirtualKeyCode::LControl,
+    VirtualKeyCode::LShift,
+    VirtualKeyCode::LWin,
+    VirtualKeyCode::Mail,
+    VirtualKeyCode::MediaSelect,
+    VirtualKeyCode::MediaStop,
+    VirtualKeyCode::Minus,
+    VirtualKeyCode::Mute,
+    VirtualKeyCode::MyComputer,
+    VirtualKeyCode::NavigateForward,
+    VirtualKeyCode::NavigateBackward,
+    VirtualKeyCode::NextTrack,
+    VirtualKeyCode::NoConvert,
+    VirtualKeyCode::OEM102,
+    VirtualKeyCode::Period,
+    VirtualKeyCode::PlayPause,
+    VirtualKeyCode::Plus,
+    VirtualKeyCode::Power,
+    VirtualKeyCode::PrevTrack,
+    VirtualKeyCode::RAlt,
+    VirtualKeyCode::RBracket,
+    VirtualKeyCode::RControl,
+    VirtualKeyCode::RShift,
+    VirtualKeyCode::RWin,
+    VirtualKeyCode::Semicolon,
+    VirtualKeyCode::Slash,
+    VirtualKeyCode::Sleep,
+    VirtualKeyCode::Stop,
+    VirtualKeyCode::Sysrq,
+    VirtualKeyCode::Tab,
+    VirtualKeyCode::Underline,
+    VirtualKeyCode::Unlabeled,
+    VirtualKeyCode::VolumeDown,
+    VirtualKeyCode::VolumeUp,
+    VirtualKeyCode::Wake,
+    VirtualKeyCode::WebBack,
+    VirtualKeyCode::WebFavorites,
+    VirtualKeyCode::WebForward,
+    VirtualKeyCode::WebHome,
+    VirtualKeyCode::WebRefresh,
+    VirtualKeyCode::WebSearch,
+    VirtualKeyCode::WebStop,
+    VirtualKeyCode::Yen,
+    VirtualKeyCode::Copy,
+    VirtualKeyCode::Paste,
+    VirtualKeyCode::Cut,
+];
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Debug)]
+pub enum Action {
+    MoveN,
+    MoveS,
+    MoveE,
+    MoveW,
+    MoveNE,
+    MoveNW,
+    MoveSE,
+    MoveSW,
+    Wait,
+    AutoExplore,
+    TravelToStairs,
+    PickUp,
+    Descend,
+    Inventory,
+    Drop,
+    Look,
+    Help,
+    SaveAndQuit,
+    MenuUp,
+    MenuDown,
+    MenuSelect,
+    MenuCancel,
+    MenuNextPage,
+    MenuPrevPage,
+    NextTarget,
+    MenuSwitchMode,
+    RollAttributes,
+}
+
+pub const GAME_ACTIONS: &[Action] = &[
+    Action::MoveN,
+    Action::MoveS,
+    Action::MoveE,
+    Action::MoveW,
+    Action::MoveNE,
+    Action::MoveNW,
+    Action::MoveSE,
+    Action::MoveSW,
+    Action::Wait,
+    Action::AutoExplore,
+    Action::TravelToStairs,
+    Action::PickUp,
+    Action::Descend,
+    Action::Inventory,
+    Action::Drop,
+    Action::Look,
+    Action::Help,
+    Action::SaveAndQuit,
+];
+
+pub const MENU_ACTIONS: &[Action] = &[
+    Action::MenuUp,
+    Action::MenuDown,
+    Action::MenuSelect,
+    Action::MenuCancel,
+    Action::MenuNextPage,
+    Action::MenuPrevPage,
+    Action::NextTarget,
+    Action::MenuSwitchMode,
+];
+
+impl Action {
+    pub fn direction(&self) -> Option<(i32, i32)> {
+        match self {
+            Action::MoveN => Some((0, -1)),
+            Action::MoveS => Some((0, 1)),
+            Action::MoveE => Some((1, 0)),
+            Action::MoveW => Some((-1, 0)),
+            Action::MoveNE => Some((1, -1)),
+            Action::MoveNW => Some((-1, -1)),
+            Action::MoveSE => Some((1, 1)),
+            Action::MoveSW => Some((-1, 1)),
+            _ => None,
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Action::MoveN => "Move north",
+            Action::MoveS => "Move south",
+            Action::MoveE => "Move east",
+            Action::MoveW => "Move west",
+            Action::MoveNE => "Move north-east",
+            Action::MoveNW => "Move north-west",
+            Action::MoveSE => "Move south-east",
+            Action::MoveSW => "Move south-west",
+            Action::Wait => "Wait a turn",
+            Action::AutoExplore => "Explore automatically",
+            Action::TravelToStairs => "Travel to the stairs down",
+            Action::PickUp => "Pick up an item",
+            Action::Descend => "Take the stairs down",
+            Action::Inventory => "Use an item",
+            Action::Drop => "Drop an item",
+            Action::Look => "Look around",
+            Action::Help => "Show this help",
+            Action::SaveAndQuit => "Save and quit",
+            Action::MenuUp => "Menu: previous entry",
+            Action::MenuDown => "Menu: next entry",
+            Action::MenuSelect => "Menu: select",
+            Action::MenuCancel => "Menu: cancel",
+            Action::MenuNextPage => "Menu: next page",
+            Action::MenuPrevPage => "Menu: previous page",
+            Action::NextTarget => "Targeting: next target",
+            Action::MenuSwitchMode => "Shop: switch buying/selling",
+            Action::RollAttributes => "New character: roll attributes",
+        }
+    }
+}
+
+/// Maps actions to the names of the keys that trigger them. Key names are
+/// the `VirtualKeyCode` variant names, e.g. "K", "Numpad8" or "Up".
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Keymap {
+    pub bindings: BTreeMap<Action, Vec<String>>,
+}
+
+impl Keymap {
+    /// Loads the default bindings, overridden per action by any found in
+    /// `keymap.json`. A file that can't be read or parsed is logged and the
+    /// defaults are used instead.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load() -> Keymap {
+        let mut keymap = Keymap::default();
+        if !Path::new(KEYMAP_FILE).exists() {
+            return keymap;
+        }
+        let overrides: BTreeMap<Action, Vec<String>> = match read_to_string(KEYMAP_FILE)
+            .map_err(|e| e.to_string())
+            .and_then(|data| serde_json::from_str(&data).map_err(|e| e.to_string()))
+        {
+            Ok(overrides) => overrides,
+            Err(e) => {
+                rltk::console::log(format!(
+                    "Ignoring {}, using the default keys: {}",
+                    KEYMAP_FILE, e
+                ));
+                return keymap;
+            }
+        };
+        let known: Vec<String> = KEY_CODES.iter().map(|key| format!("{:?}", key)).collect();
+        for (action, keys) in overrides.iter() {
+            for key in keys.iter().filter(|key| !known.contains(key)) {
+                rltk::console::log(format!(
+                    "{}: unknown key \"{}\" for {:?}",
+                    KEYMAP_FILE, key, action
+                ));
+            }
+        }
+        keymap.bindings.extend(overrides);
+        keymap
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn load() -> Keymap {
+        Keymap::default()
+    }
+
+    pub fn action_for(&self, key: VirtualKeyCode, allowed: &[Action]) -> Option<Action> {
+        let name = format!("{:?}", key);
+        allowed.iter().copied().find(|action| {
+            self.bindings
+                .get(action)
+                .is_some_and(|keys| keys.contains(&name))
+        })
+    }
+
+    pub fn keys_for(&self, action: Action) -> String {
+        match self.bindings.get(&action) {
+            Some(keys) if !keys.is_empty() => keys.join(", "),
+            _ => "(unbound)".to_string(),
+        }
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        let defaults: &[(Action, &[&str])] = &[
+            (Action::MoveN, &["K", "Numpad8", "Up"]),
+            (Action::MoveS, &["J", "Numpad2", "Down"]),
+            (Action::MoveE, &["L", "Numpad6", "Right"]),
+            (Action::MoveW, &["H", "Numpad4", "Left"]),
+            (Action::MoveNE, &["U", "Numpad7"]),
+            (Action::MoveNW, &["Y", "Numpad9"]),
+            (Action::MoveSE, &["N", "Numpad3"]),
+            (Action::MoveSW, &["B", "Numpad1"]),
+            (Action::Wait, &["Numpad5", "Space"]),
+            (Action::AutoExplore, &["X"]),
+            (Action::TravelToStairs, &["T"]),
+            (Action::PickUp, &["G"]),
+            (Action::Descend, &["Period"]),
+            (Action::Inventory, &["I"]),
+            (Action::Drop, &["D"]),
+            (Action::Look, &["Semicolon"]),
+            (Action::Help, &["Slash", "F1"]),
+            (Action::SaveAndQuit, &["Escape"]),
+            (Action::MenuUp, &["Up"]),
+            (Action::MenuDown, &["Down"]),
+            (Action::MenuSelect, &["Return"]),
+            (Action::MenuCancel, &["Escape"]),
+            (Action::MenuNextPage, &["PageDown", "Right"]),
+            (Action::MenuPrevPage, &["PageUp", "Left"]),
+            (Action::NextTarget, &["Tab"]),
+            (Action::MenuSwitchMode, &["Tab"]),
+            (Action::RollAttributes, &["R"]),
+        ];
+
+        Keymap {
+            bindings: defaults
+                .iter()
+                .map(|(action, keys)| (*action, keys.iter().map(|k| k.to_string()).collect()))
+                .collect(),
+        }
+    }
+}
@@ -1,23 +1,37 @@
+pub mod auto_move;
+pub mod boss;
+pub mod character;
 pub mod components;
 pub mod damage_system;
+pub mod fov;
 pub mod game_state;
 pub mod gamelog;
 pub mod gui;
+pub mod identification;
 pub mod inventory_system;
+pub mod keymap;
+pub mod lighting_system;
 pub mod map;
 pub mod map_indexing_system;
 pub mod melee_combat_system;
 pub mod monster_ai_system;
+pub mod morgue;
+pub mod movement_system;
+pub mod navigation;
 pub mod player;
 pub mod random_table;
 pub mod rect;
+pub mod replay;
 pub mod saveload_system;
+pub mod score;
+pub mod shop;
 pub mod spawner;
 pub mod visibility_system;
 
 pub use components::*;
 pub use gamelog::*;
-pub use map::{try_next_level, Map, MAPHEIGHT, MAPWIDTH};
+pub use map::{try_next_level, Map, FINAL_DEPTH, MAPHEIGHT, MAPWIDTH};
+pub use score::RunStats;
 pub use spawner::*;
 
 use specs::prelude::*;
@@ -29,15 +43,38 @@ pub enum RunState {
     PreRun,
     PlayerTurn,
     MonsterTurn,
-    ShowInventory,
-    ShowDropItem,
+    ShowInventory {
+        page: usize,
+    },
+    ShowDropItem {
+        page: usize,
+    },
+    ShowPickup {
+        selected: u32,
+    },
+    ShowHelp,
+    ShowShop {
+        vendor: Entity,
+        mode: gui::ShopMode,
+        page: usize,
+    },
+    ShowLook {
+        cursor: rltk::Point,
+    },
     ShowTargeting {
         range: i32,
         item: Entity,
+        cursor: rltk::Point,
+        last_mouse: rltk::Point,
     },
     MainMenu {
         menu_selection: gui::MainMenuSelection,
     },
+    CharacterCreation {
+        step: gui::CreationStep,
+    },
     SaveGame,
     NextLevel,
+    Victory,
+    GameOver,
 }
@@ -0,0 +1,44 @@
+use super::fov::{field_of_view, FovAlgorithm};
+use super::map::Map;
+use super::{LightSource, Player, Position, Viewshed};
+use rltk::{DistanceAlg, Point};
+use specs::prelude::*;
+
+/// Recomputes how brightly lit each tile is. Light fades with distance from
+/// its source and is blocked by walls, like sight.
+pub struct LightingSystem {}
+
+impl<'a> System<'a> for LightingSystem {
+    type SystemData = (
+        WriteExpect<'a, Map>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, LightSource>,
+        ReadStorage<'a, Player>,
+        WriteStorage<'a, Viewshed>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (mut map, positions, lights, player, mut viewsheds) = data;
+
+        let mut light = vec![0.0; map.tiles.len()];
+        for (pos, source) in (&positions, &lights).join() {
+            let center = Point::new(pos.x, pos.y);
+            for tile in field_of_view(FovAlgorithm::Symmetric, center, source.radius, &map) {
+                if tile.x < 0 || tile.x >= map.width || tile.y < 0 || tile.y >= map.height {
+                    continue;
+                }
+                let distance = DistanceAlg::Pythagoras.distance2d(center, tile);
+                let level = 1.0 - distance / (source.radius as f32 + 1.0);
+                let idx = map.xy_idx(tile.x, tile.y);
+                light[idx] = f32::max(light[idx], level);
+            }
+        }
+        map.light = light;
+
+        // Lights move about, so what the player can see may have changed
+        // even if they haven't.
+        for (_player, viewshed) in (&player, &mut viewsheds).join() {
+            viewshed.dirty = true;
+        }
+    }
+}
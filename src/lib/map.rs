@@ -2,6 +2,7 @@ use rltk::{Algorithm2D, BaseMap, Point, RandomNumberGenerator, Rltk, RGB};
 use serde::{Deserialize, Serialize};
 use specs::prelude::{Entity, World};
 use std::cmp::{max, min};
+use std::collections::{HashMap, HashSet};
 
 use super::rect::Rect;
 use super::GameLog;
@@ -17,6 +18,17 @@ pub const MAPWIDTH: usize = 80;
 pub const MAPHEIGHT: usize = 43;
 pub const MAPCOUNT: usize = MAPWIDTH * MAPHEIGHT;
 
+/// The deepest level. It has no way down; instead its boss guards the item
+/// that wins the game.
+pub const FINAL_DEPTH: i32 = 5;
+
+/// What the player last saw on a tile that is now out of sight.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RememberedEntity {
+    pub glyph: rltk::FontCharType,
+    pub fg: RGB,
+}
+
 #[derive(Default, Serialize, Deserialize, Clone)]
 pub struct Map {
     pub tiles: Vec<TileType>,
@@ -27,10 +39,18 @@ pub struct Map {
     pub visible_tiles: Vec<bool>,
     pub blocked: Vec<bool>,
     pub depth: i32,
+    #[serde(default)]
+    pub bloodstains: HashSet<usize>,
+    #[serde(default)]
+    pub remembered: HashMap<usize, RememberedEntity>,
 
     #[serde(skip_serializing)]
     #[serde(skip_deserializing)]
     pub tile_content: Vec<Vec<Entity>>,
+
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    pub light: Vec<f32>,
 }
 
 impl Map {
@@ -59,7 +79,7 @@ impl Map {
         for x in min(x1, x2)..=max(x1, x2) {
             let idx = self.xy_idx(x, y);
             if idx > 0 && idx < MAPCOUNT {
-                self.tiles[idx as usize] = TileType::Floor;
+                self.tiles[idx] = TileType::Floor;
             }
         }
     }
@@ -68,11 +88,20 @@ impl Map {
         for y in min(y1, y2)..=max(y1, y2) {
             let idx = self.xy_idx(x, y);
             if idx > 0 && idx < MAPCOUNT {
-                self.tiles[idx as usize] = TileType::Floor;
+                self.tiles[idx] = TileType::Floor;
             }
         }
     }
 
+    /// The background colour of a tile, which shows any decal on it.
+    pub fn background(&self, idx: usize) -> RGB {
+        if self.bloodstains.contains(&idx) {
+            RGB::from_f32(0.5, 0., 0.)
+        } else {
+            RGB::from_f32(0., 0., 0.)
+        }
+    }
+
     pub fn clear_content_index(&mut self) {
         for content in self.tile_content.iter_mut() {
             content.clear();
@@ -85,7 +114,7 @@ impl Map {
         }
     }
 
-    pub fn new_map(new_depth: i32) -> (Map, Rect) {
+    pub fn new_map(new_depth: i32, rng: &mut RandomNumberGenerator) -> (Map, Rect) {
         let mut map = Map {
             tiles: vec![TileType::Wall; MAPCOUNT],
             rooms: Vec::new(),
@@ -95,7 +124,10 @@ impl Map {
             visible_tiles: vec![false; MAPCOUNT],
             depth: new_depth,
             blocked: vec![false; MAPCOUNT],
+            bloodstains: HashSet::new(),
+            remembered: HashMap::new(),
             tile_content: vec![Vec::new(); MAPCOUNT],
+            light: vec![0.0; MAPCOUNT],
         };
         let mut first_room = Rect::new(0, 0, 0, 0);
 
@@ -103,8 +135,6 @@ impl Map {
         const MIN_SIZE: i32 = 6;
         const MAX_SIZE: i32 = 10;
 
-        let mut rng = RandomNumberGenerator::new();
-
         for _ in 0..MAX_ROOMS {
             let w = rng.range(MIN_SIZE, MAX_SIZE);
             let h = rng.range(MIN_SIZE, MAX_SIZE);
@@ -139,9 +169,11 @@ impl Map {
             }
         }
 
-        let stairs_position = map.rooms[map.rooms.len() - 1].center();
-        let stairs_idx = map.xy_idx(stairs_position.0, stairs_position.1);
-        map.tiles[stairs_idx] = TileType::DownStairs;
+        if new_depth < FINAL_DEPTH {
+            let stairs_position = map.rooms[map.rooms.len() - 1].center();
+            let stairs_idx = map.xy_idx(stairs_position.0, stairs_position.1);
+            map.tiles[stairs_idx] = TileType::DownStairs;
+        }
 
         (map, first_room)
     }
@@ -155,7 +187,7 @@ impl Algorithm2D for Map {
 
 impl BaseMap for Map {
     fn is_opaque(&self, idx: usize) -> bool {
-        self.tiles[idx as usize] == TileType::Wall
+        self.tiles[idx] == TileType::Wall
     }
 
     fn get_available_exits(&self, idx: usize) -> rltk::SmallVec<[(usize, f32); 10]> {
@@ -212,10 +244,22 @@ pub fn draw_map(ecs: &World, ctx: &mut Rltk) {
                 TileType::Wall => (rltk::to_cp437('#'), RGB::from_f32(0.0, 1.0, 0.0)),
                 TileType::DownStairs => (rltk::to_cp437('>'), RGB::from_f32(0.0, 1.0, 1.0)),
             };
-            if !map.visible_tiles[idx] {
+            let mut bg = map.background(idx);
+            if map.visible_tiles[idx] {
+                let shade = 0.3 + 0.7 * map.light[idx];
+                fg = fg * shade;
+                bg = bg * shade;
+            } else {
                 fg = fg.to_greyscale();
+                bg = bg.to_greyscale();
+            }
+            ctx.set(x, y, fg, bg, glyph);
+
+            if !map.visible_tiles[idx] {
+                if let Some(memory) = map.remembered.get(&idx) {
+                    ctx.set(x, y, memory.fg.to_greyscale() * 0.6, bg, memory.glyph);
+                }
             }
-            ctx.set(x, y, fg, RGB::from_f32(0., 0., 0.), glyph);
         }
 
         x += 1;
@@ -1,31 +1,68 @@
-use super::{gamelog::GameLog, CombatStats, Name, SufferDamage, WantsToMelee};
+use super::{
+    gamelog::GameLog, CombatStats, DefenseBonus, Equipped, MeleePowerBonus, Name, Rarity,
+    SufferDamage, WantsToMelee,
+};
 use specs::prelude::*;
 
 pub struct MeleeCombatSystem {}
 
 type MeleeData<'a> = (
     Entities<'a>,
+    ReadExpect<'a, Entity>,
     WriteExpect<'a, GameLog>,
     WriteStorage<'a, WantsToMelee>,
     ReadStorage<'a, Name>,
     ReadStorage<'a, CombatStats>,
     WriteStorage<'a, SufferDamage>,
+    ReadStorage<'a, Equipped>,
+    ReadStorage<'a, MeleePowerBonus>,
+    ReadStorage<'a, DefenseBonus>,
+    ReadStorage<'a, Rarity>,
 );
 
 impl<'a> System<'a> for MeleeCombatSystem {
     type SystemData = MeleeData<'a>;
 
     fn run(&mut self, data: Self::SystemData) {
-        let (entities, mut log, mut wants_melee, names, combat_stats, mut inflict_damage) = data;
+        let (
+            entities,
+            player_entity,
+            mut log,
+            mut wants_melee,
+            names,
+            combat_stats,
+            mut inflict_damage,
+            equipped,
+            melee_bonuses,
+            defense_bonuses,
+            rarities,
+        ) = data;
+        let with_rarity = |item: Entity, base: i32| {
+            rarities
+                .get(item)
+                .map_or(base, |rarity| rarity.tier.bonus(base))
+        };
 
-        for (_entity, wants_melee, name, stats) in
+        for (entity, wants_melee, name, stats) in
             (&entities, &wants_melee, &names, &combat_stats).join()
         {
             if stats.hp > 0 {
                 let target_stats = combat_stats.get(wants_melee.target).unwrap();
                 if target_stats.hp > 0 {
                     let target_name = names.get(wants_melee.target).unwrap();
-                    let damage = i32::max(0, stats.power - target_stats.defense);
+                    let power: i32 = stats.power
+                        + (&entities, &equipped, &melee_bonuses)
+                            .join()
+                            .filter(|(_, worn, _)| worn.owner == entity)
+                            .map(|(item, _, bonus)| with_rarity(item, bonus.power))
+                            .sum::<i32>();
+                    let defense: i32 = target_stats.defense
+                        + (&entities, &equipped, &defense_bonuses)
+                            .join()
+                            .filter(|(_, worn, _)| worn.owner == wants_melee.target)
+                            .map(|(item, _, bonus)| with_rarity(item, bonus.defense))
+                            .sum::<i32>();
+                    let damage = i32::max(0, power - defense);
 
                     if damage == 0 {
                         log.entries.push(format!(
@@ -37,7 +74,13 @@ impl<'a> System<'a> for MeleeCombatSystem {
                             "{} hits {} for {} hp",
                             &name.name, &target_name.name, damage
                         ));
-                        SufferDamage::new_damage(&mut inflict_damage, wants_melee.target, damage);
+                        SufferDamage::new_damage(
+                            &mut inflict_damage,
+                            wants_melee.target,
+                            damage,
+                            name.name.clone(),
+                            entity == *player_entity,
+                        );
                     }
                 }
             }
@@ -45,3 +88,60 @@ impl<'a> System<'a> for MeleeCombatSystem {
         wants_melee.clear();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{register_components, EquipmentSlot, RarityTier};
+
+    fn fighter(ecs: &mut World, name: &str) -> Entity {
+        ecs.create_entity()
+            .with(Name {
+                name: name.to_string(),
+            })
+            .with(CombatStats {
+                max_hp: 30,
+                hp: 30,
+                defense: 0,
+                power: 5,
+            })
+            .build()
+    }
+
+    /// Damage from one hit by someone wielding a dagger of the given tier.
+    fn hit_with_dagger(tier: RarityTier) -> i32 {
+        let mut ecs = World::new();
+        register_components(&mut ecs);
+        ecs.insert(GameLog {
+            entries: Vec::new(),
+        });
+        let attacker = fighter(&mut ecs, "Attacker");
+        let target = fighter(&mut ecs, "Target");
+        ecs.insert(attacker);
+        ecs.create_entity()
+            .with(MeleePowerBonus { power: 2 })
+            .with(Rarity {
+                tier,
+                disguise: None,
+            })
+            .with(Equipped {
+                owner: attacker,
+                slot: EquipmentSlot::Melee,
+            })
+            .build();
+        ecs.write_storage::<WantsToMelee>()
+            .insert(attacker, WantsToMelee { target })
+            .unwrap();
+
+        MeleeCombatSystem {}.run_now(&ecs);
+        let damage = ecs.read_storage::<SufferDamage>();
+        damage.get(target).map_or(0, |hit| hit.amount.iter().sum())
+    }
+
+    #[test]
+    fn rarity_adjusts_the_bonus_of_worn_gear() {
+        assert_eq!(hit_with_dagger(RarityTier::Common), 7);
+        assert_eq!(hit_with_dagger(RarityTier::Rare), 9);
+        assert_eq!(hit_with_dagger(RarityTier::Cursed), 3);
+    }
+}
@@ -1,20 +1,37 @@
-use crate::{Confusion, Map, Monster, Position, RunState, Viewshed, WantsToMelee};
+use crate::navigation;
+use crate::{
+    AreaOfEffect, Boss, CombatStats, Confusion, InBackpack, InflictsDamage, Item, Map, Monster,
+    Position, ProvidesHealing, Ranged, RunState, UsesItems, Viewshed, WantsToMelee, WantsToMove,
+    WantsToPickupItem, WantsToUseItem,
+};
 use rltk::Point;
 use specs::prelude::*;
 
 pub struct MonsterAI {}
 
 type MonsterAIType<'a> = (
-    WriteExpect<'a, Map>,
+    ReadExpect<'a, Map>,
     ReadExpect<'a, Point>,
     ReadExpect<'a, Entity>,
     ReadExpect<'a, RunState>,
     Entities<'a>,
-    WriteStorage<'a, Viewshed>,
+    ReadStorage<'a, Viewshed>,
     ReadStorage<'a, Monster>,
-    WriteStorage<'a, Position>,
+    ReadStorage<'a, Position>,
     WriteStorage<'a, WantsToMelee>,
     WriteStorage<'a, Confusion>,
+    ReadStorage<'a, UsesItems>,
+    ReadStorage<'a, CombatStats>,
+    ReadStorage<'a, InBackpack>,
+    ReadStorage<'a, Item>,
+    ReadStorage<'a, ProvidesHealing>,
+    ReadStorage<'a, Ranged>,
+    ReadStorage<'a, InflictsDamage>,
+    ReadStorage<'a, AreaOfEffect>,
+    WriteStorage<'a, WantsToUseItem>,
+    WriteStorage<'a, WantsToPickupItem>,
+    WriteStorage<'a, WantsToMove>,
+    ReadStorage<'a, Boss>,
 );
 
 impl<'a> System<'a> for MonsterAI {
@@ -22,24 +39,39 @@ impl<'a> System<'a> for MonsterAI {
 
     fn run(&mut self, data: Self::SystemData) {
         let (
-            mut map,
+            map,
             player_pos,
             player_entity,
             run_state,
             entities,
-            mut viewshed,
+            viewshed,
             monster,
-            mut position,
+            position,
             mut wants_to_melee,
             mut confused,
+            item_users,
+            combat_stats,
+            backpack,
+            items,
+            healing,
+            ranged,
+            inflicts_damage,
+            aoe,
+            mut wants_use,
+            mut wants_pickup,
+            mut wants_to_move,
+            bosses,
         ) = data;
 
         if *run_state != RunState::MonsterTurn {
             return;
         }
 
-        for (entity, mut viewshed, _monster, mut pos) in
-            (&entities, &mut viewshed, &monster, &mut position).join()
+        // Built at most once a turn, and only if some monster needs them.
+        let mut approach: Option<Vec<i32>> = None;
+        let mut flee: Option<Vec<i32>> = None;
+
+        for (entity, viewshed, _monster, pos) in (&entities, &viewshed, &monster, &position).join()
         {
             if let Some(confuzzled) = confused.get_mut(entity) {
                 if confuzzled.turns < 2 {
@@ -50,10 +82,93 @@ impl<'a> System<'a> for MonsterAI {
                 continue;
             }
 
+            if item_users.get(entity).is_some() {
+                let carried: Vec<Entity> = (&entities, &backpack)
+                    .join()
+                    .filter(|(_, pack)| pack.owner == entity)
+                    .map(|(item, _)| item)
+                    .collect();
+
+                // Drink a healing potion when badly hurt.
+                let hurt = combat_stats
+                    .get(entity)
+                    .is_some_and(|stats| stats.hp < stats.max_hp / 2);
+                let potion = carried.iter().find(|item| healing.get(**item).is_some());
+                if let (true, Some(potion)) = (hurt, potion) {
+                    wants_use
+                        .insert(
+                            entity,
+                            WantsToUseItem {
+                                item: *potion,
+                                target: None,
+                            },
+                        )
+                        .expect("Unable to insert intent");
+                    continue;
+                }
+
+                // Throw a damaging scroll at the player from a safe distance.
+                if viewshed.visible_tiles.contains(&*player_pos) {
+                    let distance = rltk::DistanceAlg::Pythagoras
+                        .distance2d(Point::new(pos.x, pos.y), *player_pos);
+                    let scroll = carried.iter().find(|item| {
+                        inflicts_damage.get(**item).is_some()
+                            && ranged
+                                .get(**item)
+                                .is_some_and(|r| distance <= r.range as f32)
+                            && aoe
+                                .get(**item)
+                                .is_none_or(|area| distance > area.radius as f32)
+                    });
+                    if let (true, Some(scroll)) = (distance >= 1.5, scroll) {
+                        wants_use
+                            .insert(
+                                entity,
+                                WantsToUseItem {
+                                    item: *scroll,
+                                    target: Some(*player_pos),
+                                },
+                            )
+                            .expect("Unable to insert intent");
+                        continue;
+                    }
+                }
+
+                // Pick up a potion or attack scroll lying underfoot, unless
+                // the player is close enough to hit.
+                let adjacent = rltk::DistanceAlg::Pythagoras
+                    .distance2d(Point::new(pos.x, pos.y), *player_pos)
+                    < 1.5;
+                let idx = map.xy_idx(pos.x, pos.y);
+                let underfoot = map.tile_content[idx].iter().find(|e| {
+                    items.get(**e).is_some()
+                        && (healing.get(**e).is_some()
+                            || (inflicts_damage.get(**e).is_some() && ranged.get(**e).is_some()))
+                });
+                if let (false, Some(item)) = (adjacent, underfoot) {
+                    wants_pickup
+                        .insert(
+                            *item,
+                            WantsToPickupItem {
+                                collected_by: entity,
+                                item: *item,
+                            },
+                        )
+                        .expect("Unable to insert intent");
+                    continue;
+                }
+            }
+
             if viewshed.visible_tiles.contains(&*player_pos) {
+                // Badly hurt monsters run for it instead of fighting on,
+                // except the boss, which stands its ground.
+                let fleeing = bosses.get(entity).is_none()
+                    && combat_stats
+                        .get(entity)
+                        .is_some_and(|stats| stats.hp < stats.max_hp / 4);
                 let distance =
                     rltk::DistanceAlg::Pythagoras.distance2d(Point::new(pos.x, pos.y), *player_pos);
-                if distance < 1.5 {
+                if distance < 1.5 && !fleeing {
                     wants_to_melee
                         .insert(
                             entity,
@@ -62,20 +177,27 @@ impl<'a> System<'a> for MonsterAI {
                             },
                         )
                         .expect("unable to insert");
-                } else if viewshed.visible_tiles.contains(&*player_pos) {
-                    let path = rltk::a_star_search(
-                        map.xy_idx(pos.x, pos.y) as i32,
-                        map.xy_idx(player_pos.x, player_pos.y) as i32,
-                        &*map,
-                    );
-                    if path.success && path.steps.len() > 1 {
-                        let mut idx = map.xy_idx(pos.x, pos.y);
-                        map.blocked[idx] = false;
-                        pos.x = path.steps[1] as i32 % map.width;
-                        pos.y = path.steps[1] as i32 / map.width;
-                        idx = map.xy_idx(pos.x, pos.y);
-                        map.blocked[idx] = true;
-                        viewshed.dirty = true;
+                } else {
+                    let approach =
+                        approach.get_or_insert_with(|| navigation::approach_map(&map, *player_pos));
+                    let step = if fleeing {
+                        let flee = flee.get_or_insert_with(|| navigation::flee_map(&map, approach));
+                        navigation::downhill(&map, flee, Point::new(pos.x, pos.y))
+                    } else {
+                        navigation::downhill(&map, approach, Point::new(pos.x, pos.y))
+                    };
+                    if let Some(next) = step {
+                        wants_to_move
+                            .insert(
+                                entity,
+                                WantsToMove {
+                                    destination: Point::new(
+                                        next as i32 % map.width,
+                                        next as i32 / map.width,
+                                    ),
+                                },
+                            )
+                            .expect("Unable to insert intent");
                     }
                 }
             }
@@ -0,0 +1,122 @@
+use crate::identification::Identification;
+use crate::map::TileType;
+use crate::score::ScoreEntry;
+use crate::{CombatStats, Equipped, GameLog, InBackpack, Map, Name};
+use rltk::Point;
+use specs::prelude::*;
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs::File;
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::Write;
+
+/// How many of the newest log messages make it into the dump.
+const LOG_LINES: usize = 10;
+
+/// The file a run's morgue dump is written to, named by seed so earlier
+/// runs aren't overwritten.
+pub fn morgue_file(seed: u64) -> String {
+    format!("./morgue-{}.txt", seed)
+}
+
+/// A plain-text account of a finished run: how it ended, the player's
+/// stats and kit, the last messages and as much of the final level as they
+/// had seen.
+pub fn morgue_text(ecs: &World, entry: &ScoreEntry) -> String {
+    let player = *ecs.fetch::<Entity>();
+    let mut lines: Vec<String> = Vec::new();
+
+    lines.push(format!("{}, {}", entry.name, entry.cause));
+    lines.push(format!("Seed: {}", entry.seed));
+    lines.push(format!(
+        "Depth {}, {} turns, {} kills, {} gold. Score: {}",
+        entry.depth, entry.turns, entry.kills, entry.gold, entry.score
+    ));
+    if let Some(stats) = ecs.read_storage::<CombatStats>().get(player) {
+        lines.push(format!(
+            "HP {}/{}, power {}, defense {}",
+            stats.hp, stats.max_hp, stats.power, stats.defense
+        ));
+    }
+
+    lines.push(String::new());
+    lines.push("Inventory:".to_string());
+    {
+        let identification = ecs.fetch::<Identification>();
+        let names = ecs.read_storage::<Name>();
+        let backpack = ecs.read_storage::<InBackpack>();
+        let equipped = ecs.read_storage::<Equipped>();
+        let entities = ecs.entities();
+        let mut carried: Vec<(i32, String)> = (&entities, &backpack, &names)
+            .join()
+            .filter(|(_, pack, _)| pack.owner == player)
+            .map(|(item, pack, name)| {
+                let worn = if equipped.get(item).is_some() {
+                    " (worn)"
+                } else {
+                    ""
+                };
+                (
+                    pack.order,
+                    format!("  {}{}", identification.display_name(&name.name), worn),
+                )
+            })
+            .collect();
+        carried.sort();
+        if carried.is_empty() {
+            lines.push("  (nothing)".to_string());
+        }
+        lines.extend(carried.into_iter().map(|(_, line)| line));
+    }
+
+    lines.push(String::new());
+    lines.push("Last messages:".to_string());
+    {
+        let log = ecs.fetch::<GameLog>();
+        let skip = log.entries.len().saturating_sub(LOG_LINES);
+        lines.extend(
+            log.entries
+                .iter()
+                .skip(skip)
+                .map(|line| format!("  {}", line)),
+        );
+    }
+
+    lines.push(String::new());
+    lines.push("Final map:".to_string());
+    {
+        let map = ecs.fetch::<Map>();
+        let player_pos = *ecs.fetch::<Point>();
+        for y in 0..map.height {
+            let row: String = (0..map.width)
+                .map(|x| {
+                    let idx = map.xy_idx(x, y);
+                    if x == player_pos.x && y == player_pos.y {
+                        '@'
+                    } else if !map.revealed_tiles[idx] {
+                        ' '
+                    } else {
+                        match map.tiles[idx] {
+                            TileType::Floor => '.',
+                            TileType::Wall => '#',
+                            TileType::DownStairs => '>',
+                        }
+                    }
+                })
+                .collect();
+            lines.push(row.trim_end().to_string());
+        }
+    }
+
+    lines.join("\n") + "\n"
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn write_morgue(_ecs: &World, _entry: &ScoreEntry) {}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn write_morgue(ecs: &World, entry: &ScoreEntry) {
+    let mut writer = File::create(morgue_file(entry.seed)).expect("Unable to create morgue file");
+    writer
+        .write_all(morgue_text(ecs, entry).as_bytes())
+        .expect("Unable to write morgue file");
+}
@@ -0,0 +1,72 @@
+use super::{BlocksTile, Map, Position, Viewshed, WantsToMove};
+use rltk::Point;
+use specs::prelude::*;
+
+/// Carries out every move asked for this turn, by the player and monsters
+/// alike. Moves are settled one at a time against the map as it stands
+/// after the moves before them, so two movers can never end up on the same
+/// tile; whoever comes second stays put.
+pub struct MovementSystem {}
+
+type MovementData<'a> = (
+    WriteExpect<'a, Map>,
+    WriteExpect<'a, Point>,
+    ReadExpect<'a, Entity>,
+    Entities<'a>,
+    WriteStorage<'a, WantsToMove>,
+    WriteStorage<'a, Position>,
+    WriteStorage<'a, Viewshed>,
+    ReadStorage<'a, BlocksTile>,
+);
+
+impl<'a> System<'a> for MovementSystem {
+    type SystemData = MovementData<'a>;
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (
+            mut map,
+            mut player_pos,
+            player_entity,
+            entities,
+            mut wants_move,
+            mut positions,
+            mut viewsheds,
+            blockers,
+        ) = data;
+
+        for (entity, intent, pos) in (&entities, &wants_move, &mut positions).join() {
+            let destination = intent.destination;
+            if destination.x < 1
+                || destination.x > map.width - 1
+                || destination.y < 1
+                || destination.y > map.height - 1
+            {
+                continue;
+            }
+
+            let from = map.xy_idx(pos.x, pos.y);
+            let to = map.xy_idx(destination.x, destination.y);
+            if map.blocked[to] {
+                continue;
+            }
+
+            if blockers.get(entity).is_some() {
+                map.blocked[from] = false;
+                map.blocked[to] = true;
+            }
+            map.tile_content[from].retain(|e| *e != entity);
+            map.tile_content[to].push(entity);
+
+            pos.x = destination.x;
+            pos.y = destination.y;
+            if let Some(viewshed) = viewsheds.get_mut(entity) {
+                viewshed.dirty = true;
+            }
+            if entity == *player_entity {
+                *player_pos = destination;
+            }
+        }
+
+        wants_move.clear();
+    }
+}
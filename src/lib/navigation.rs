@@ -0,0 +1,181 @@
+use crate::map::{Map, TileType};
+use rltk::Point;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Step costs, in tenths of a tile, matching the diagonal cost the map
+/// uses for pathfinding.
+const STRAIGHT: i32 = 10;
+const DIAGONAL: i32 = 14;
+const UNREACHABLE: i32 = i32::MAX;
+
+/// How much further monsters will run to get away rather than just backing
+/// off. Anything above one makes them prefer escape routes over corners.
+const FLEE_FACTOR: (i32, i32) = (-12, 10);
+
+const NEIGHBOURS: [(i32, i32, i32); 8] = [
+    (-1, 0, STRAIGHT),
+    (1, 0, STRAIGHT),
+    (0, -1, STRAIGHT),
+    (0, 1, STRAIGHT),
+    (-1, -1, DIAGONAL),
+    (1, -1, DIAGONAL),
+    (-1, 1, DIAGONAL),
+    (1, 1, DIAGONAL),
+];
+
+fn neighbours(map: &Map, idx: usize) -> impl Iterator<Item = (usize, i32)> + '_ {
+    let (x, y) = (idx as i32 % map.width, idx as i32 / map.width);
+    NEIGHBOURS.iter().filter_map(move |(dx, dy, cost)| {
+        let (nx, ny) = (x + dx, y + dy);
+        if nx < 1 || nx > map.width - 1 || ny < 1 || ny > map.height - 1 {
+            return None;
+        }
+        let n = map.xy_idx(nx, ny);
+        if map.tiles[n] == TileType::Wall {
+            return None;
+        }
+        Some((n, *cost))
+    })
+}
+
+/// Relaxes every tile to the cheapest of its own value and a neighbour's
+/// value plus the step cost.
+fn relax(map: &Map, mut values: Vec<i32>) -> Vec<i32> {
+    let mut open: BinaryHeap<Reverse<(i32, usize)>> = values
+        .iter()
+        .enumerate()
+        .filter(|(_, value)| **value != UNREACHABLE)
+        .map(|(idx, value)| Reverse((*value, idx)))
+        .collect();
+
+    while let Some(Reverse((value, idx))) = open.pop() {
+        if value > values[idx] {
+            continue;
+        }
+        for (n, cost) in neighbours(map, idx) {
+            if value + cost < values[n] {
+                values[n] = value + cost;
+                open.push(Reverse((values[n], n)));
+            }
+        }
+    }
+
+    values
+}
+
+/// Distances to `target` through everything but walls. Monsters don't
+/// count as obstacles here, so the map stays true however crowded it gets.
+pub fn approach_map(map: &Map, target: Point) -> Vec<i32> {
+    let mut values = vec![UNREACHABLE; map.tiles.len()];
+    values[map.xy_idx(target.x, target.y)] = 0;
+    relax(map, values)
+}
+
+/// Turns an approach map into one that leads away from its target. Scaling
+/// by a negative factor and relaxing again means the lowest ground is far
+/// away and well connected, not in the nearest dead end.
+pub fn flee_map(map: &Map, approach: &[i32]) -> Vec<i32> {
+    let values = approach
+        .iter()
+        .map(|value| match *value {
+            UNREACHABLE => UNREACHABLE,
+            value => value * FLEE_FACTOR.0 / FLEE_FACTOR.1,
+        })
+        .collect();
+    relax(map, values)
+}
+
+/// The free neighbouring tile that goes furthest downhill from `from`, if
+/// any is lower than where it stands. Tiles held by other creatures are
+/// skipped, so a monster with nowhere better to go waits its turn instead
+/// of shuffling sideways.
+pub fn downhill(map: &Map, values: &[i32], from: Point) -> Option<usize> {
+    let here = values[map.xy_idx(from.x, from.y)];
+    neighbours(map, map.xy_idx(from.x, from.y))
+        .filter(|(n, _)| !map.blocked[*n] && values[*n] < here)
+        .min_by_key(|(n, _)| values[*n])
+        .map(|(n, _)| n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A map drawn as rows of `#` for walls and `.` for floor.
+    fn map_from(rows: &[&str]) -> Map {
+        let tiles: Vec<TileType> = rows
+            .iter()
+            .flat_map(|row| row.chars())
+            .map(|c| {
+                if c == '#' {
+                    TileType::Wall
+                } else {
+                    TileType::Floor
+                }
+            })
+            .collect();
+        Map {
+            width: rows[0].len() as i32,
+            height: rows.len() as i32,
+            blocked: tiles.iter().map(|tile| *tile == TileType::Wall).collect(),
+            tiles,
+            ..Map::default()
+        }
+    }
+
+    #[rustfmt::skip]
+    const ROOM: [&str; 5] = [
+        "#######",
+        "#.....#",
+        "#.....#",
+        "#.#####",
+        "#######",
+    ];
+
+    #[test]
+    fn approach_maps_count_steps_around_walls() {
+        let map = map_from(&ROOM);
+        let values = approach_map(&map, Point::new(1, 1));
+        let at = |x, y| values[map.xy_idx(x, y)];
+
+        assert_eq!(at(1, 1), 0);
+        assert_eq!(at(4, 1), 3 * STRAIGHT);
+        assert_eq!(at(2, 2), DIAGONAL);
+        assert_eq!(at(5, 2), DIAGONAL + 3 * STRAIGHT);
+        assert_eq!(at(1, 3), 2 * STRAIGHT);
+        assert_eq!(at(3, 3), UNREACHABLE);
+    }
+
+    #[test]
+    fn monsters_step_downhill_around_anyone_in_the_way() {
+        let mut map = map_from(&ROOM);
+        let values = approach_map(&map, Point::new(1, 1));
+
+        assert_eq!(
+            downhill(&map, &values, Point::new(3, 2)),
+            Some(map.xy_idx(2, 1))
+        );
+        let in_the_way = map.xy_idx(2, 1);
+        map.blocked[in_the_way] = true;
+        assert_eq!(
+            downhill(&map, &values, Point::new(3, 2)),
+            Some(map.xy_idx(2, 2))
+        );
+        assert_eq!(downhill(&map, &values, Point::new(1, 1)), None);
+    }
+
+    #[test]
+    fn flee_maps_lead_away_from_the_target() {
+        let map = map_from(&ROOM);
+        let approach = approach_map(&map, Point::new(1, 1));
+        let flee = flee_map(&map, &approach);
+
+        let mut pos = Point::new(2, 1);
+        while let Some(next) = downhill(&map, &flee, pos) {
+            assert!(approach[next] > approach[map.xy_idx(pos.x, pos.y)]);
+            pos = Point::new(next as i32 % map.width, next as i32 / map.width);
+        }
+        assert_eq!(approach[map.xy_idx(pos.x, pos.y)], DIAGONAL + 3 * STRAIGHT);
+    }
+}
@@ -1,97 +1,167 @@
+use crate::auto_move::{self, AutoMoveMode};
+use crate::keymap::{Action, Keymap, GAME_ACTIONS};
+use crate::replay::{self, Command};
 use crate::*;
-use rltk::{Point, Rltk, VirtualKeyCode};
+use rltk::{Point, Rltk};
 use specs::prelude::*;
-use std::cmp::{max, min};
 
 pub fn player_input(gs: &mut State, ctx: &mut Rltk) -> RunState {
-    // Player movement
-    match ctx.key {
-        None => RunState::AwaitingInput,
-        Some(key) => match key {
-            VirtualKeyCode::H | VirtualKeyCode::Numpad4 | VirtualKeyCode::Left => {
-                try_move_player(-1, 0, &mut gs.ecs);
-                RunState::PlayerTurn
-            }
-            VirtualKeyCode::L | VirtualKeyCode::Numpad6 | VirtualKeyCode::Right => {
-                try_move_player(1, 0, &mut gs.ecs);
-                RunState::PlayerTurn
-            }
-            VirtualKeyCode::K | VirtualKeyCode::Numpad8 | VirtualKeyCode::Up => {
-                try_move_player(0, -1, &mut gs.ecs);
-                RunState::PlayerTurn
-            }
-            VirtualKeyCode::J | VirtualKeyCode::Numpad2 | VirtualKeyCode::Down => {
-                try_move_player(0, 1, &mut gs.ecs);
-                RunState::PlayerTurn
-            }
+    if ctx.left_click {
+        let (x, y) = ctx.mouse_pos();
+        auto_move::travel_to(&mut gs.ecs, Point::new(x, y));
+        return RunState::AwaitingInput;
+    }
 
-            VirtualKeyCode::Y | VirtualKeyCode::Numpad9 => {
-                try_move_player(-1, -1, &mut gs.ecs);
-                RunState::PlayerTurn
-            }
+    let key = match ctx.key {
+        None => return RunState::AwaitingInput,
+        Some(key) => key,
+    };
+    let action = gs.ecs.fetch::<Keymap>().action_for(key, GAME_ACTIONS);
 
-            VirtualKeyCode::U | VirtualKeyCode::Numpad7 => {
-                try_move_player(1, -1, &mut gs.ecs);
-                RunState::PlayerTurn
-            }
+    let command = match action {
+        Some(action) if action.direction().is_some() => {
+            let (dx, dy) = action.direction().unwrap();
+            Command::Move { dx, dy }
+        }
 
-            VirtualKeyCode::N | VirtualKeyCode::Numpad3 => {
-                try_move_player(1, 1, &mut gs.ecs);
-                RunState::PlayerTurn
+        Some(Action::PickUp) => {
+            let items = items_underfoot(&gs.ecs);
+            match items.len() {
+                0 => {
+                    gs.ecs
+                        .fetch_mut::<GameLog>()
+                        .entries
+                        .push("There is nothing here to pick up.".to_string());
+                    return RunState::AwaitingInput;
+                }
+                1 => Command::PickUp {
+                    items: vec![items[0].id()],
+                },
+                _ => return RunState::ShowPickup { selected: 0 },
             }
+        }
+        Some(Action::Descend) => Command::Descend,
+        Some(Action::Wait) => Command::Wait,
+        Some(Action::AutoExplore) => {
+            auto_move::start(&mut gs.ecs, AutoMoveMode::Explore);
+            return RunState::AwaitingInput;
+        }
+        Some(Action::TravelToStairs) => {
+            auto_move::travel_to_stairs(&mut gs.ecs);
+            return RunState::AwaitingInput;
+        }
 
-            VirtualKeyCode::B | VirtualKeyCode::Numpad1 => {
-                try_move_player(-1, 1, &mut gs.ecs);
-                RunState::PlayerTurn
+        Some(Action::Inventory) => return RunState::ShowInventory { page: 0 },
+        Some(Action::Drop) => return RunState::ShowDropItem { page: 0 },
+        Some(Action::Look) => {
+            return RunState::ShowLook {
+                cursor: *gs.ecs.fetch::<Point>(),
             }
+        }
+        Some(Action::Help) => return RunState::ShowHelp,
+        Some(Action::SaveAndQuit) => return RunState::SaveGame,
 
-            VirtualKeyCode::G => {
-                get_item(&mut gs.ecs);
-                RunState::PlayerTurn
-            }
+        // Unbound keys, and modifiers pressed on their own, do nothing.
+        _ => return RunState::AwaitingInput,
+    };
 
-            VirtualKeyCode::Period => {
-                if try_next_level(&mut gs.ecs) {
-                    return RunState::NextLevel;
-                }
-                RunState::PlayerTurn
-            }
+    if let Command::Move { dx, dy } = command {
+        if ctx.shift {
+            auto_move::start(&mut gs.ecs, AutoMoveMode::Run { dx, dy });
+            return RunState::AwaitingInput;
+        }
+    }
 
-            VirtualKeyCode::I => RunState::ShowInventory,
-            VirtualKeyCode::D => RunState::ShowDropItem,
-            VirtualKeyCode::Escape => RunState::SaveGame,
-            VirtualKeyCode::Numpad5 | VirtualKeyCode::Space => skip_turn(&mut gs.ecs),
+    perform_command(&mut gs.ecs, command)
+}
 
-            _ => RunState::PlayerTurn,
+pub fn perform_command(ecs: &mut World, command: Command) -> RunState {
+    replay::record(ecs, &command);
+
+    match command {
+        Command::Move { dx, dy } => match try_move_player(dx, dy, ecs) {
+            Some(vendor) => RunState::ShowShop {
+                vendor,
+                mode: gui::ShopMode::Buy,
+                page: 0,
+            },
+            None => RunState::PlayerTurn,
         },
+        Command::PickUp { items } => {
+            pick_up(ecs, &items);
+            RunState::PlayerTurn
+        }
+        Command::Descend => {
+            if try_next_level(ecs) {
+                return RunState::NextLevel;
+            }
+            RunState::PlayerTurn
+        }
+        Command::Wait => skip_turn(ecs),
+        Command::Idle => RunState::PlayerTurn,
+        Command::UseItem { item, target } => {
+            let item = ecs.entities().entity(item);
+            let mut intent = ecs.write_storage::<WantsToUseItem>();
+            intent
+                .insert(*ecs.fetch::<Entity>(), WantsToUseItem { item, target })
+                .expect("Unable to insert intent");
+            RunState::PlayerTurn
+        }
+        Command::DropItem { item } => {
+            let item = ecs.entities().entity(item);
+            let mut intent = ecs.write_storage::<WantsToDropItem>();
+            intent
+                .insert(*ecs.fetch::<Entity>(), WantsToDropItem { item })
+                .expect("Unable to insert intent");
+            RunState::PlayerTurn
+        }
+        Command::Buy { vendor, item } => {
+            let vendor = ecs.entities().entity(vendor);
+            let item = ecs.entities().entity(item);
+            shop::buy(ecs, vendor, item);
+            RunState::ShowShop {
+                vendor,
+                mode: gui::ShopMode::Buy,
+                page: 0,
+            }
+        }
+        Command::Sell { vendor, item } => {
+            let vendor = ecs.entities().entity(vendor);
+            let item = ecs.entities().entity(item);
+            shop::sell(ecs, vendor, item);
+            RunState::ShowShop {
+                vendor,
+                mode: gui::ShopMode::Sell,
+                page: 0,
+            }
+        }
     }
 }
 
-fn try_move_player(delta_x: i32, delta_y: i32, ecs: &mut World) {
-    let mut positions = ecs.write_storage::<Position>();
-    let mut players = ecs.write_storage::<Player>();
-    let mut viewsheds = ecs.write_storage::<Viewshed>();
-    let mut ppos = ecs.write_resource::<Point>();
-
+/// Moves or attacks in the given direction, returning the vendor if the
+/// player bumped into one.
+fn try_move_player(delta_x: i32, delta_y: i32, ecs: &mut World) -> Option<Entity> {
+    let positions = ecs.read_storage::<Position>();
+    let players = ecs.read_storage::<Player>();
     let entities = ecs.entities();
     let mut wants_to_melee = ecs.write_storage::<WantsToMelee>();
+    let mut wants_to_move = ecs.write_storage::<WantsToMove>();
 
     let combat_stats = ecs.read_storage::<CombatStats>();
+    let vendors = ecs.read_storage::<Vendor>();
     let map = ecs.fetch::<Map>();
 
-    for (entity, _player, pos, viewshed) in
-        (&entities, &mut players, &mut positions, &mut viewsheds).join()
-    {
-        if pos.x + delta_x < 1
-            || pos.x + delta_x > map.width - 1
-            || pos.y + delta_y < 1
-            || pos.y + delta_y > map.width - 1
-        {
-            return;
+    for (entity, _player, pos) in (&entities, &players, &positions).join() {
+        let (x, y) = (pos.x + delta_x, pos.y + delta_y);
+        if x < 1 || x > map.width - 1 || y < 1 || y > map.height - 1 {
+            return None;
         }
-        let destination_idx = map.xy_idx(pos.x + delta_x, pos.y + delta_y);
+        let destination_idx = map.xy_idx(x, y);
 
         for potential_target in map.tile_content[destination_idx].iter() {
+            if vendors.get(*potential_target).is_some() {
+                return Some(*potential_target);
+            }
             if combat_stats.get(*potential_target).is_some() {
                 wants_to_melee
                     .insert(
@@ -101,51 +171,54 @@ fn try_move_player(delta_x: i32, delta_y: i32, ecs: &mut World) {
                         },
                     )
                     .expect("Add target failed");
-                return;
+                return None;
             }
         }
         if !map.blocked[destination_idx] {
-            pos.x = min(79, max(0, pos.x + delta_x));
-            pos.y = min(49, max(0, pos.y + delta_y));
-            ppos.x = pos.x;
-            ppos.y = pos.y;
-            viewshed.dirty = true;
+            wants_to_move
+                .insert(
+                    entity,
+                    WantsToMove {
+                        destination: Point::new(x, y),
+                    },
+                )
+                .expect("Unable to insert intent");
         }
     }
+
+    None
 }
 
-fn get_item(ecs: &mut World) {
+/// Items lying on the player's tile, in a stable order.
+pub fn items_underfoot(ecs: &World) -> Vec<Entity> {
     let player_pos = ecs.fetch::<Point>();
-    let player_entity = ecs.fetch::<Entity>();
     let entities = ecs.entities();
-    let items = ecs.read_storage::<Position>();
+    let items = ecs.read_storage::<Item>();
     let positions = ecs.read_storage::<Position>();
-    let mut gamelog = ecs.fetch_mut::<GameLog>();
 
-    let mut target_item: Option<Entity> = None;
-    for (item_entity, _item, position) in (&entities, &items, &positions).join() {
-        if position.x == player_pos.x && position.y == player_pos.y && item_entity != *player_entity
-        {
-            target_item = Some(item_entity);
-        }
-    }
+    (&entities, &items, &positions)
+        .join()
+        .filter(|(_, _, pos)| pos.x == player_pos.x && pos.y == player_pos.y)
+        .map(|(entity, _, _)| entity)
+        .collect()
+}
 
-    match target_item {
-        None => gamelog
-            .entries
-            .push("There is nothing here to pick up.".to_string()),
-        Some(item) => {
-            let mut pickup = ecs.write_storage::<WantsToPickupItem>();
-            pickup
-                .insert(
-                    *player_entity,
-                    WantsToPickupItem {
-                        collected_by: *player_entity,
-                        item,
-                    },
-                )
-                .expect("Unalbe to insert want to pickup");
-        }
+fn pick_up(ecs: &mut World, items: &[u32]) {
+    let player_entity = ecs.fetch::<Entity>();
+    let entities = ecs.entities();
+    let mut pickup = ecs.write_storage::<WantsToPickupItem>();
+
+    for item in items.iter() {
+        let item = entities.entity(*item);
+        pickup
+            .insert(
+                item,
+                WantsToPickupItem {
+                    collected_by: *player_entity,
+                    item,
+                },
+            )
+            .expect("Unable to insert want to pickup");
     }
 }
 
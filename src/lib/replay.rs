@@ -0,0 +1,205 @@
+use crate::character::CharacterSheet;
+use rltk::Point;
+use serde::{Deserialize, Serialize};
+use specs::prelude::*;
+use std::collections::VecDeque;
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs::{read_to_string, File, OpenOptions};
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::Write;
+
+#[cfg(not(target_arch = "wasm32"))]
+const REPLAY_FILE: &str = "./replay.jsonl";
+
+/// A single state-changing action taken by the player. Menus and other
+/// purely cosmetic input never show up here.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub enum Command {
+    Move {
+        dx: i32,
+        dy: i32,
+    },
+    PickUp {
+        items: Vec<u32>,
+    },
+    Descend,
+    Wait,
+    /// An unbound key, which used to pass the turn. Nothing records it any
+    /// more, but replays that hold it still play back.
+    Idle,
+    UseItem {
+        item: u32,
+        target: Option<Point>,
+    },
+    DropItem {
+        item: u32,
+    },
+    Buy {
+        vendor: u32,
+        item: u32,
+    },
+    Sell {
+        vendor: u32,
+        item: u32,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct ReplayHeader {
+    seed: u64,
+    #[serde(default)]
+    character: CharacterSheet,
+}
+
+pub struct Replay {
+    pub seed: u64,
+    pub character: CharacterSheet,
+    pub recording: bool,
+    /// True for a run started from a replay file. Nothing it does is saved,
+    /// even once the recorded commands have run out.
+    pub replaying: bool,
+    pub playback: VecDeque<Command>,
+}
+
+impl Replay {
+    pub fn new(seed: u64) -> Replay {
+        Replay {
+            seed,
+            character: CharacterSheet::default(),
+            recording: false,
+            replaying: false,
+            playback: VecDeque::new(),
+        }
+    }
+
+    pub fn next_command(&mut self) -> Option<Command> {
+        self.playback.pop_front()
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn start_recording(_ecs: &mut World) {}
+
+/// Starts a fresh replay file for the current run, headed by its seed and
+/// the character it was played with.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn start_recording(ecs: &mut World) {
+    let mut replay = ecs.fetch_mut::<Replay>();
+    let mut writer = File::create(REPLAY_FILE).expect("Unable to create replay file");
+    let header = ReplayHeader {
+        seed: replay.seed,
+        character: replay.character.clone(),
+    };
+    serde_json::to_writer(&mut writer, &header).expect("Unable to write replay header");
+    writeln!(writer).expect("Unable to write replay header");
+    replay.recording = true;
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn record(_ecs: &World, _command: &Command) {}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn record(ecs: &World, command: &Command) {
+    if !ecs.fetch::<Replay>().recording {
+        return;
+    }
+    let mut writer = OpenOptions::new()
+        .append(true)
+        .open(REPLAY_FILE)
+        .expect("Unable to open replay file");
+    serde_json::to_writer(&mut writer, command).expect("Unable to record command");
+    writeln!(writer).expect("Unable to record command");
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn load_replay(_path: &str) -> Result<Replay, String> {
+    Err("Replays are not supported on the web".to_string())
+}
+
+/// Reads a replay file, returning the seed and character the run was
+/// started with and a `Replay` that will feed the recorded commands back in
+/// order, or a description of what's wrong with the file.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn load_replay(path: &str) -> Result<Replay, String> {
+    let data = read_to_string(path).map_err(|e| format!("Unable to read replay file: {}", e))?;
+    parse_replay(&data)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn parse_replay(data: &str) -> Result<Replay, String> {
+    let mut lines = (1..)
+        .zip(data.lines())
+        .filter(|(_, line)| !line.trim().is_empty());
+
+    let (_, header) = lines.next().ok_or("Empty replay file")?;
+    let header: ReplayHeader =
+        serde_json::from_str(header).map_err(|e| format!("Invalid replay header: {}", e))?;
+    let mut replay = Replay::new(header.seed);
+    replay.character = header.character;
+    replay.replaying = true;
+    for (number, line) in lines {
+        let command = serde_json::from_str(line)
+            .map_err(|e| format!("Invalid replay command on line {}: {}", number, e))?;
+        replay.playback.push_back(command);
+    }
+
+    Ok(replay)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_replay_holds_its_seed_character_and_commands() {
+        let replay = parse_replay(
+            r#"{"seed":42,"character":{"name":"Ada","class":"Mage","strength":9,"toughness":11}}
+"Wait"
+
+{"Move":{"dx":1,"dy":-1}}
+{"UseItem":{"item":7,"target":{"x":3,"y":4}}}
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(replay.seed, 42);
+        assert_eq!(replay.character.name, "Ada");
+        assert!(replay.replaying);
+        assert_eq!(
+            Vec::from(replay.playback),
+            [
+                Command::Wait,
+                Command::Move { dx: 1, dy: -1 },
+                Command::UseItem {
+                    item: 7,
+                    target: Some(Point::new(3, 4)),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn a_header_without_a_character_plays_the_default_one() {
+        let replay = parse_replay(r#"{"seed":1}"#).unwrap();
+        assert_eq!(replay.character.name, CharacterSheet::default().name);
+        assert!(replay.playback.is_empty());
+    }
+
+    #[test]
+    fn a_bad_replay_says_what_is_wrong() {
+        assert_eq!(parse_replay("\n\n").err().unwrap(), "Empty replay file");
+        assert!(parse_replay("Wait")
+            .err()
+            .unwrap()
+            .starts_with("Invalid replay header"));
+
+        let error = parse_replay("{\"seed\":1}\n\"Wait\"\n\n\"Dance\"\n")
+            .err()
+            .unwrap();
+        assert!(
+            error.starts_with("Invalid replay command on line 4"),
+            "{}",
+            error
+        );
+    }
+}
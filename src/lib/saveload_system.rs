@@ -3,61 +3,204 @@ use crate::*;
 use specs::saveload::{DeserializeComponents, MarkedBuilder, SerializeComponents};
 #[cfg(not(target_arch = "wasm32"))]
 use std::fs::{read_to_string, File};
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::Write;
 use std::path::Path;
 
+const SAVE_FILE: &str = "./savegame.json";
+#[cfg(not(target_arch = "wasm32"))]
+const SAVE_TEMP_FILE: &str = "./savegame.json.tmp";
+const SAVE_BACKUP_FILE: &str = "./savegame.json.bak";
+#[cfg(not(target_arch = "wasm32"))]
+const SETTINGS_FILE: &str = "./settings.json";
+
+pub const DEFAULT_AUTOSAVE_INTERVAL: i32 = 100;
+
+pub struct AutosaveSettings {
+    pub every_n_turns: i32,
+    pub turns_since_save: i32,
+}
+
+impl AutosaveSettings {
+    pub fn new(every_n_turns: i32) -> AutosaveSettings {
+        AutosaveSettings {
+            every_n_turns,
+            turns_since_save: 0,
+        }
+    }
+
+    /// Counts a completed turn, returning true when an autosave is due.
+    /// An interval of zero or less disables turn-based autosaves.
+    pub fn tick(&mut self) -> bool {
+        if self.every_n_turns <= 0 {
+            return false;
+        }
+        self.turns_since_save += 1;
+        if self.turns_since_save >= self.every_n_turns {
+            self.turns_since_save = 0;
+            return true;
+        }
+        false
+    }
+}
+
+/// The parts of `settings.json` that control saving. Anything missing keeps
+/// its default.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(serde::Deserialize)]
+struct SaveSettingsFile {
+    autosave_interval: Option<i32>,
+}
+
+impl AutosaveSettings {
+    /// Reads the autosave interval from `settings.json`, e.g.
+    /// `{"autosave_interval": 50}`. A missing or broken file keeps the
+    /// default.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load() -> AutosaveSettings {
+        let data = match read_to_string(SETTINGS_FILE) {
+            Ok(data) => data,
+            Err(_) => return AutosaveSettings::default(),
+        };
+        match serde_json::from_str::<SaveSettingsFile>(&data) {
+            Ok(settings) => AutosaveSettings::new(
+                settings
+                    .autosave_interval
+                    .unwrap_or(DEFAULT_AUTOSAVE_INTERVAL),
+            ),
+            Err(e) => {
+                rltk::console::log(format!("Ignoring invalid {}: {}", SETTINGS_FILE, e));
+                AutosaveSettings::default()
+            }
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn load() -> AutosaveSettings {
+        AutosaveSettings::default()
+    }
+}
+
+impl Default for AutosaveSettings {
+    fn default() -> AutosaveSettings {
+        AutosaveSettings::new(DEFAULT_AUTOSAVE_INTERVAL)
+    }
+}
+
+/// Saves are a JSON object holding each component's storage under the
+/// component's name, so a save still loads after components are added.
 #[cfg(not(target_arch = "wasm32"))]
 macro_rules! serialize_individually {
-    ($ecs:expr, $ser:expr, $data:expr, $($type:ty),*) => {
+    ($ecs:expr, $saved:expr, $data:expr, $($type:ty),*) => {
          $(
-             SerializeComponents::<NoError, SimpleMarker<SerializeMe>>::serialize(
-                 &( $ecs.read_storage::<$type>(), ),
-                 &$data.0,
-                 &$data.1,
-                 &mut $ser,
-             )
-             .unwrap();
+             $saved.insert(
+                 stringify!($type).to_string(),
+                 SerializeComponents::<NoError, SimpleMarker<SerializeMe>>::serialize(
+                     &( $ecs.read_storage::<$type>(), ),
+                     &$data.0,
+                     &$data.1,
+                     serde_json::value::Serializer,
+                 )
+                 .unwrap(),
+             );
              )*
     };
 }
 
+/// Components missing from the save are left empty.
 #[cfg(not(target_arch = "wasm32"))]
 macro_rules! deserialize_individually {
-    ($ecs:expr, $de:expr, $data:expr, $($type:ty),*) => {
+    ($ecs:expr, $saved:expr, $data:expr, $($type:ty),*) => {
          $(
-             DeserializeComponents::<NoError, SimpleMarker<SerializeMe>>::deserialize(
-                 &mut ( &mut $ecs.write_storage::<$type>(), ),
-                 &$data.0,
-                 &mut $data.1,
-                 &mut $data.2,
-                 &mut $de,
-             )
-             .unwrap();
+             if let Some(storage) = $saved.get(stringify!($type)) {
+                 DeserializeComponents::<NoError, SimpleMarker<SerializeMe>>::deserialize(
+                     &mut ( &mut $ecs.write_storage::<$type>(), ),
+                     &$data.0,
+                     &mut $data.1,
+                     &mut $data.2,
+                     storage,
+                 )
+                 .map_err(|e| format!("{}: {}", stringify!($type), e))?;
+             }
              )*
     };
 }
 
+/// The components the original game saved, in order. Its saves are just
+/// these storages one after the other, without names.
+#[cfg(not(target_arch = "wasm32"))]
+const LEGACY_SAVE_LAYOUT: [&str; 22] = [
+    "Position",
+    "Renderable",
+    "Player",
+    "Viewshed",
+    "Monster",
+    "Name",
+    "BlocksTile",
+    "CombatStats",
+    "SufferDamage",
+    "WantsToMelee",
+    "Item",
+    "Consumable",
+    "Ranged",
+    "InflictsDamage",
+    "AreaOfEffect",
+    "Confusion",
+    "ProvidesHealing",
+    "InBackpack",
+    "WantsToPickupItem",
+    "WantsToUseItem",
+    "WantsToDropItem",
+    "SerializationHelper",
+];
+
 #[cfg(target_arch = "wasm32")]
 pub fn save_game(_ecs: &mut World) {}
 
 #[cfg(not(target_arch = "wasm32"))]
 pub fn save_game(ecs: &mut World) {
+    let data = save_data(ecs);
+    let mut writer = File::create(SAVE_TEMP_FILE).unwrap();
+    writer
+        .write_all(data.as_bytes())
+        .expect("Unable to write save file");
+    writer.sync_all().expect("Unable to flush save file");
+
+    // Only replace the real save once the new one is fully on disk, keeping
+    // the previous save around as a backup.
+    if Path::new(SAVE_FILE).exists() {
+        std::fs::copy(SAVE_FILE, SAVE_BACKUP_FILE).expect("Unable to back up save");
+    }
+    std::fs::rename(SAVE_TEMP_FILE, SAVE_FILE).expect("Unable to replace save");
+}
+
+/// The current game, as written to the save file.
+#[cfg(not(target_arch = "wasm32"))]
+fn save_data(ecs: &mut World) -> String {
     let mapcopy = ecs.get_mut::<super::map::Map>().unwrap().clone();
+    let identification = (*ecs.fetch::<Identification>()).clone();
+    let stats = (*ecs.fetch::<RunStats>()).clone();
+    let seed = ecs.fetch::<replay::Replay>().seed;
     let savehelper = ecs
         .create_entity()
-        .with(SerializationHelper { map: mapcopy })
+        .with(SerializationHelper {
+            map: mapcopy,
+            identification,
+            stats,
+            seed,
+        })
         .marked::<SimpleMarker<SerializeMe>>()
         .build();
 
-    {
+    let data = {
         let data = (
             ecs.entities(),
             ecs.read_storage::<SimpleMarker<SerializeMe>>(),
         );
-        let writer = File::create("./savegame.json").unwrap();
-        let mut serializer = serde_json::Serializer::new(writer);
+        let mut saved = serde_json::Map::new();
         serialize_individually!(
             ecs,
-            serializer,
+            saved,
             data,
             Position,
             Renderable,
@@ -69,6 +212,7 @@ pub fn save_game(ecs: &mut World) {
             CombatStats,
             SufferDamage,
             WantsToMelee,
+            WantsToMove,
             Item,
             Consumable,
             Ranged,
@@ -80,34 +224,95 @@ pub fn save_game(ecs: &mut World) {
             WantsToPickupItem,
             WantsToUseItem,
             WantsToDropItem,
+            Description,
+            Weight,
+            Attributes,
+            Rarity,
+            Equippable,
+            Equipped,
+            MeleePowerBonus,
+            DefenseBonus,
+            RemovesCurse,
+            Wallet,
+            Gold,
+            Price,
+            Vendor,
+            LootTable,
+            UsesItems,
+            ProvidesFood,
+            LightSource,
+            Boss,
+            GoalItem,
             SerializationHelper
         );
-    }
+        serde_json::to_string(&saved).expect("Unable to write save file")
+    };
 
     ecs.delete_entity(savehelper).expect("Crash on cleanup");
+    data
 }
 
+/// Present after the last attempt to load a game failed, so the main menu
+/// can say so.
+pub struct LoadFailed;
+
+/// True if there is a save or, failing that, a backup to load.
 pub fn does_save_exist() -> bool {
-    Path::new("./savegame.json").exists()
+    Path::new(SAVE_FILE).exists() || Path::new(SAVE_BACKUP_FILE).exists()
 }
 
 #[cfg(target_arch = "wasm32")]
-pub fn load_game(_ecs: &mut World) {}
+pub fn load_game(_ecs: &mut World) -> Result<(), String> {
+    Err("Saves are not supported on the web".to_string())
+}
 
+/// Loads the save, or the backup if the save itself can't be loaded. If
+/// neither can, the world is left empty and the reasons are returned.
 #[cfg(not(target_arch = "wasm32"))]
-pub fn load_game(ecs: &mut World) {
-    {
-        let mut to_delete = Vec::new();
-        for e in ecs.entities().join() {
-            to_delete.push(e);
-        }
-        for del in to_delete.iter() {
-            ecs.delete_entity(*del).expect("Deletion failed");
+pub fn load_game(ecs: &mut World) -> Result<(), String> {
+    let mut errors = Vec::new();
+    for path in [SAVE_FILE, SAVE_BACKUP_FILE] {
+        let loaded = read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|data| load_save_data(ecs, &data));
+        match loaded {
+            Ok(()) => return Ok(()),
+            Err(e) => errors.push(format!("{}: {}", path, e)),
         }
     }
+    clear_world(ecs);
+    Err(errors.join("; "))
+}
 
-    let data = read_to_string("./savegame.json").unwrap();
-    let mut de = serde_json::Deserializer::from_str(&data);
+#[cfg(not(target_arch = "wasm32"))]
+fn clear_world(ecs: &mut World) {
+    ecs.delete_all();
+    ecs.insert(SimpleMarkerAllocator::<SerializeMe>::new());
+}
+
+/// The storages in a save, by component name. Saves from the original
+/// game are unnamed storages in `LEGACY_SAVE_LAYOUT` order.
+#[cfg(not(target_arch = "wasm32"))]
+fn saved_storages(data: &str) -> Result<serde_json::Map<String, serde_json::Value>, String> {
+    let values = serde_json::Deserializer::from_str(data)
+        .into_iter::<serde_json::Value>()
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    match values.as_slice() {
+        [serde_json::Value::Object(saved)] => Ok(saved.clone()),
+        legacy if legacy.len() == LEGACY_SAVE_LAYOUT.len() => Ok(LEGACY_SAVE_LAYOUT
+            .iter()
+            .map(|name| name.to_string())
+            .zip(values)
+            .collect()),
+        _ => Err("not a save this version can read".to_string()),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn load_save_data(ecs: &mut World, data: &str) -> Result<(), String> {
+    let saved = saved_storages(data)?;
+    clear_world(ecs);
 
     {
         let mut d = (
@@ -118,7 +323,7 @@ pub fn load_game(ecs: &mut World) {
 
         deserialize_individually!(
             ecs,
-            de,
+            saved,
             d,
             Position,
             Renderable,
@@ -130,6 +335,7 @@ pub fn load_game(ecs: &mut World) {
             CombatStats,
             SufferDamage,
             WantsToMelee,
+            WantsToMove,
             Item,
             Consumable,
             Ranged,
@@ -141,6 +347,25 @@ pub fn load_game(ecs: &mut World) {
             WantsToPickupItem,
             WantsToUseItem,
             WantsToDropItem,
+            Description,
+            Weight,
+            Attributes,
+            Rarity,
+            Equippable,
+            Equipped,
+            MeleePowerBonus,
+            DefenseBonus,
+            RemovesCurse,
+            Wallet,
+            Gold,
+            Price,
+            Vendor,
+            LootTable,
+            UsesItems,
+            ProvidesFood,
+            LightSource,
+            Boss,
+            GoalItem,
             SerializationHelper
         );
     }
@@ -155,6 +380,10 @@ pub fn load_game(ecs: &mut World) {
             let mut worldmap = ecs.write_resource::<super::map::Map>();
             *worldmap = h.map.clone();
             worldmap.tile_content = vec![Vec::new(); super::map::MAPCOUNT];
+            worldmap.light = vec![0.0; super::map::MAPCOUNT];
+            *ecs.write_resource::<Identification>() = h.identification.clone();
+            *ecs.write_resource::<RunStats>() = h.stats.clone();
+            ecs.write_resource::<replay::Replay>().seed = h.seed;
             deleteme = Some(e);
         }
         for (e, _, pos) in (&entities, &player, &position).join() {
@@ -165,12 +394,161 @@ pub fn load_game(ecs: &mut World) {
         }
     }
 
-    ecs.delete_entity(deleteme.unwrap())
-        .expect("Unable to delete helper");
+    let helper = deleteme.ok_or("the save holds no game")?;
+    ecs.delete_entity(helper).expect("Unable to delete helper");
+    Ok(())
 }
 
+/// Removes the save along with its backup, so a finished or resumed run
+/// can't be loaded again.
 pub fn delete_save() {
-    if Path::new("./savegame.json").exists() {
-        std::fs::remove_file("./savegame.json").expect("Unable to delete file");
+    for path in [SAVE_FILE, SAVE_BACKUP_FILE] {
+        if Path::new(path).exists() {
+            std::fs::remove_file(path).expect("Unable to delete file");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_world(seed: u64) -> State {
+        let mut gs = State { ecs: World::new() };
+        gs.ecs.insert(replay::Replay::new(seed));
+        gs.ecs
+            .insert(AutosaveSettings::new(DEFAULT_AUTOSAVE_INTERVAL));
+        gs.new_game();
+        gs
+    }
+
+    /// Puts the first item on the floor into the player's backpack, so the
+    /// save has something carried in it.
+    fn carry_an_item(ecs: &mut World) {
+        let player = *ecs.fetch::<Entity>();
+        let item = {
+            let entities = ecs.entities();
+            let items = ecs.read_storage::<Item>();
+            let positions = ecs.read_storage::<Position>();
+            (&entities, &items, &positions).join().next().unwrap().0
+        };
+        ecs.write_storage::<Position>().remove(item);
+        ecs.write_storage::<InBackpack>()
+            .insert(
+                item,
+                InBackpack {
+                    owner: player,
+                    order: 3,
+                },
+            )
+            .unwrap();
+    }
+
+    type Snapshot = Vec<(u64, String, Option<(i32, i32)>, Option<i32>, bool)>;
+
+    /// Every saved entity by marker: its name, where it is, its place in a
+    /// backpack and whether it's the player.
+    fn snapshot(ecs: &World) -> Snapshot {
+        let entities = ecs.entities();
+        let markers = ecs.read_storage::<SimpleMarker<SerializeMe>>();
+        let names = ecs.read_storage::<Name>();
+        let positions = ecs.read_storage::<Position>();
+        let backpacks = ecs.read_storage::<InBackpack>();
+        let player = *ecs.fetch::<Entity>();
+        let mut snapshot: Snapshot = (&entities, &markers, &names)
+            .join()
+            .map(|(entity, marker, name)| {
+                (
+                    marker.id(),
+                    name.name.clone(),
+                    positions.get(entity).map(|pos| (pos.x, pos.y)),
+                    backpacks.get(entity).map(|carried| carried.order),
+                    entity == player,
+                )
+            })
+            .collect();
+        snapshot.sort();
+        snapshot
+    }
+
+    /// Rewrites a save the way the original game wrote it: bare storages in
+    /// `LEGACY_SAVE_LAYOUT` order, without anything added since.
+    fn as_legacy_save(data: &str) -> String {
+        let mut saved: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(data).unwrap();
+        LEGACY_SAVE_LAYOUT
+            .iter()
+            .map(|name| {
+                let mut storage = saved.remove(*name).unwrap();
+                for entry in storage.as_array_mut().unwrap() {
+                    for component in entry["components"].as_array_mut().unwrap() {
+                        if let Some(fields) = component.as_object_mut() {
+                            let added: &[&str] = match *name {
+                                "Viewshed" => &["algorithm"],
+                                "SufferDamage" => &["causes"],
+                                "InBackpack" => &["order"],
+                                "SerializationHelper" => &["identification", "stats", "seed"],
+                                _ => &[],
+                            };
+                            for field in added {
+                                fields.remove(*field);
+                            }
+                            if let Some(map) = fields.get_mut("map") {
+                                let map = map.as_object_mut().unwrap();
+                                map.remove("bloodstains");
+                                map.remove("remembered");
+                            }
+                        }
+                    }
+                }
+                storage.to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_save_loads_back_into_the_same_game() {
+        let mut saved = new_world(1);
+        carry_an_item(&mut saved.ecs);
+        let data = save_data(&mut saved.ecs);
+
+        let mut loaded = new_world(2);
+        load_save_data(&mut loaded.ecs, &data).unwrap();
+
+        assert_eq!(snapshot(&loaded.ecs), snapshot(&saved.ecs));
+        assert_eq!(
+            *loaded.ecs.fetch::<rltk::Point>(),
+            *saved.ecs.fetch::<rltk::Point>()
+        );
+        assert!(loaded.ecs.fetch::<Map>().tiles == saved.ecs.fetch::<Map>().tiles);
+        assert_eq!(loaded.ecs.fetch::<replay::Replay>().seed, 1);
+    }
+
+    #[test]
+    fn a_save_from_the_original_game_still_loads() {
+        let mut saved = new_world(1);
+        carry_an_item(&mut saved.ecs);
+        let data = as_legacy_save(&save_data(&mut saved.ecs));
+
+        let mut loaded = new_world(2);
+        load_save_data(&mut loaded.ecs, &data).unwrap();
+
+        let expected: Snapshot = snapshot(&saved.ecs)
+            .into_iter()
+            .map(|(marker, name, pos, carried, player)| {
+                (marker, name, pos, carried.map(|_| 0), player)
+            })
+            .collect();
+        assert_eq!(snapshot(&loaded.ecs), expected);
+        assert!(loaded.ecs.fetch::<Map>().tiles == saved.ecs.fetch::<Map>().tiles);
+    }
+
+    #[test]
+    fn garbage_is_an_error_and_not_a_panic() {
+        let mut gs = new_world(1);
+        assert!(load_save_data(&mut gs.ecs, "").is_err());
+        assert!(load_save_data(&mut gs.ecs, "{\"Position\": 3}").is_err());
+        assert!(load_save_data(&mut gs.ecs, "[] [] [").is_err());
+        assert!(load_save_data(&mut gs.ecs, "{}").is_err());
     }
 }
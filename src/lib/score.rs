@@ -0,0 +1,146 @@
+use crate::replay::Replay;
+use crate::{GoalItem, InBackpack, Map, Name, Wallet};
+use serde::{Deserialize, Serialize};
+use specs::prelude::*;
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs::{read_to_string, OpenOptions};
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::Write;
+
+#[cfg(not(target_arch = "wasm32"))]
+const SCORE_FILE: &str = "./scores.jsonl";
+
+/// Points for winning, on top of everything else.
+const VICTORY_BONUS: i32 = 1000;
+
+/// How many runs the main menu's high score table shows.
+pub const HIGH_SCORES_SHOWN: usize = 10;
+
+/// Running totals for the current game.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct RunStats {
+    pub turns: i32,
+    pub kills: i32,
+    /// What dealt the killing blow, once the player has died.
+    #[serde(default)]
+    pub cause_of_death: Option<String>,
+}
+
+/// How a finished run went, as written to the score file.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ScoreEntry {
+    pub name: String,
+    pub depth: i32,
+    pub turns: i32,
+    pub kills: i32,
+    pub gold: i32,
+    pub won: bool,
+    pub score: i32,
+    /// How the run ended, e.g. "Killed by Goblin".
+    #[serde(default)]
+    pub cause: String,
+    #[serde(default)]
+    pub seed: u64,
+}
+
+/// The high score table as last read from the score file, so the main menu
+/// doesn't go back to disk every frame.
+pub struct HighScores {
+    pub entries: Vec<ScoreEntry>,
+}
+
+impl HighScores {
+    pub fn load() -> HighScores {
+        HighScores {
+            entries: high_scores(HIGH_SCORES_SHOWN),
+        }
+    }
+}
+
+/// True once the player is carrying the goal item.
+pub fn has_won(ecs: &World) -> bool {
+    let player = *ecs.fetch::<Entity>();
+    let goals = ecs.read_storage::<GoalItem>();
+    let backpack = ecs.read_storage::<InBackpack>();
+    (&goals, &backpack)
+        .join()
+        .any(|(_, pack)| pack.owner == player)
+}
+
+/// Sums up the run so far. Going deeper counts for the most, then kills and
+/// whatever gold the player has hung on to.
+pub fn final_score(ecs: &World, won: bool) -> ScoreEntry {
+    let player = *ecs.fetch::<Entity>();
+    let stats = ecs.fetch::<RunStats>();
+    let name = ecs
+        .read_storage::<Name>()
+        .get(player)
+        .map_or_else(String::new, |name| name.name.clone());
+    let gold = ecs
+        .read_storage::<Wallet>()
+        .get(player)
+        .map_or(0, |wallet| wallet.gold);
+    let depth = ecs.fetch::<Map>().depth;
+    let cause = if won {
+        "Escaped with the Iron Crown".to_string()
+    } else {
+        match &stats.cause_of_death {
+            Some(killer) => format!("Killed by {}", killer),
+            None => "Died".to_string(),
+        }
+    };
+
+    let mut score = depth * 100 + stats.kills * 10 + gold;
+    if won {
+        score += VICTORY_BONUS;
+    }
+
+    ScoreEntry {
+        name,
+        depth,
+        turns: stats.turns,
+        kills: stats.kills,
+        gold,
+        won,
+        score,
+        cause,
+        seed: ecs.fetch::<Replay>().seed,
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn record_score(_entry: &ScoreEntry) {}
+
+/// Appends a finished run to the score file.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn record_score(entry: &ScoreEntry) {
+    let mut writer = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(SCORE_FILE)
+        .expect("Unable to open score file");
+    serde_json::to_writer(&mut writer, entry).expect("Unable to record score");
+    writeln!(writer).expect("Unable to record score");
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn high_scores(_count: usize) -> Vec<ScoreEntry> {
+    Vec::new()
+}
+
+/// The best `count` runs from the score file, highest first. Lines that
+/// can't be read are skipped rather than losing the whole table.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn high_scores(count: usize) -> Vec<ScoreEntry> {
+    let data = match read_to_string(SCORE_FILE) {
+        Ok(data) => data,
+        Err(_) => return Vec::new(),
+    };
+    let mut entries: Vec<ScoreEntry> = data
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.score));
+    entries.truncate(count);
+    entries
+}
@@ -0,0 +1,216 @@
+use crate::identification::Identification;
+use crate::inventory_system::stack_order;
+use crate::{
+    Attributes, Equippable, Equipped, GameLog, InBackpack, Name, Price, Rarity, Wallet, Weight,
+};
+use specs::prelude::*;
+
+/// Buys one of a vendor's wares for the player, if they can pay for it and
+/// carry it.
+pub fn buy(ecs: &mut World, vendor: Entity, item: Entity) {
+    let player_entity = *ecs.fetch::<Entity>();
+    let entities = ecs.entities();
+    let names = ecs.read_storage::<Name>();
+    let prices = ecs.read_storage::<Price>();
+    let weights = ecs.read_storage::<Weight>();
+    let rarities = ecs.read_storage::<Rarity>();
+    let equippables = ecs.read_storage::<Equippable>();
+    let mut backpack = ecs.write_storage::<InBackpack>();
+    let mut wallets = ecs.write_storage::<Wallet>();
+    let identification = ecs.fetch::<Identification>();
+    let mut gamelog = ecs.fetch_mut::<GameLog>();
+
+    if backpack.get(item).is_none_or(|pack| pack.owner != vendor) {
+        return;
+    }
+    let name = identification.display_name(&names.get(item).unwrap().name);
+    let price = prices.get(item).map_or(0, |price| price.value);
+    let gold = wallets.get(player_entity).map_or(0, |wallet| wallet.gold);
+    if gold < price {
+        gamelog
+            .entries
+            .push(format!("You can't afford the {}.", name));
+        return;
+    }
+
+    if let Some(attr) = ecs.read_storage::<Attributes>().get(player_entity) {
+        let carried: i32 = (&backpack, &weights)
+            .join()
+            .filter(|(pack, _)| pack.owner == player_entity)
+            .map(|(_, weight)| weight.weight)
+            .sum();
+        let item_weight = weights.get(item).map_or(0, |w| w.weight);
+        if carried + item_weight > attr.carry_capacity() {
+            gamelog
+                .entries
+                .push(format!("You can't carry the {}: it's too heavy.", name));
+            return;
+        }
+    }
+
+    let order = stack_order(
+        (&entities, &backpack, &names)
+            .join()
+            .filter(|(_, pack, _)| pack.owner == player_entity)
+            .map(|(carried, pack, name)| {
+                (
+                    pack.order,
+                    name.name.as_str(),
+                    rarities.get(carried).map(|r| r.tier),
+                )
+            }),
+        &names.get(item).unwrap().name,
+        rarities.get(item).map(|r| r.tier),
+        equippables.get(item).is_none(),
+    );
+    backpack
+        .insert(
+            item,
+            InBackpack {
+                owner: player_entity,
+                order,
+            },
+        )
+        .expect("Unable to insert backpack entry");
+    if let Some(wallet) = wallets.get_mut(player_entity) {
+        wallet.gold -= price;
+    }
+    gamelog
+        .entries
+        .push(format!("You buy the {} for {} gold.", name, price));
+}
+
+/// Sells one of the player's items to a vendor, who pays half its price.
+pub fn sell(ecs: &mut World, vendor: Entity, item: Entity) {
+    let player_entity = *ecs.fetch::<Entity>();
+    let names = ecs.read_storage::<Name>();
+    let prices = ecs.read_storage::<Price>();
+    let equipped = ecs.read_storage::<Equipped>();
+    let mut backpack = ecs.write_storage::<InBackpack>();
+    let mut wallets = ecs.write_storage::<Wallet>();
+    let identification = ecs.fetch::<Identification>();
+    let mut gamelog = ecs.fetch_mut::<GameLog>();
+
+    if backpack
+        .get(item)
+        .is_none_or(|pack| pack.owner != player_entity)
+    {
+        return;
+    }
+    let name = identification.display_name(&names.get(item).unwrap().name);
+    if equipped.get(item).is_some() {
+        gamelog
+            .entries
+            .push(format!("You need to take off the {} first.", name));
+        return;
+    }
+    let price = match prices.get(item) {
+        Some(price) => price.sale_value(),
+        None => {
+            gamelog
+                .entries
+                .push(format!("The shopkeeper has no interest in the {}.", name));
+            return;
+        }
+    };
+
+    let order = (&backpack)
+        .join()
+        .filter(|pack| pack.owner == vendor)
+        .map(|pack| pack.order + 1)
+        .max()
+        .unwrap_or(0);
+    backpack
+        .insert(
+            item,
+            InBackpack {
+                owner: vendor,
+                order,
+            },
+        )
+        .expect("Unable to insert backpack entry");
+    if let Some(wallet) = wallets.get_mut(player_entity) {
+        wallet.gold += price;
+    }
+    gamelog
+        .entries
+        .push(format!("You sell the {} for {} gold.", name, price));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{register_components, Vendor};
+
+    /// A player with `gold` coins and a vendor with nothing for sale.
+    fn shop(gold: i32) -> (World, Entity, Entity) {
+        let mut ecs = World::new();
+        register_components(&mut ecs);
+        ecs.insert(GameLog {
+            entries: Vec::new(),
+        });
+        ecs.insert(Identification::default());
+        let player = ecs.create_entity().with(Wallet { gold }).build();
+        ecs.insert(player);
+        let vendor = ecs.create_entity().with(Vendor {}).build();
+        (ecs, player, vendor)
+    }
+
+    fn ware(ecs: &mut World, owner: Entity, price: Option<i32>) -> Entity {
+        let mut item = ecs
+            .create_entity()
+            .with(Name {
+                name: "Health Potion".to_string(),
+            })
+            .with(InBackpack { owner, order: 0 });
+        if let Some(value) = price {
+            item = item.with(Price { value });
+        }
+        item.build()
+    }
+
+    fn gold(ecs: &World, player: Entity) -> i32 {
+        ecs.read_storage::<Wallet>().get(player).unwrap().gold
+    }
+
+    fn owner(ecs: &World, item: Entity) -> Entity {
+        ecs.read_storage::<InBackpack>().get(item).unwrap().owner
+    }
+
+    #[test]
+    fn vendors_pay_half_price_and_at_least_a_coin() {
+        assert_eq!(Price { value: 30 }.sale_value(), 15);
+        assert_eq!(Price { value: 25 }.sale_value(), 12);
+        assert_eq!(Price { value: 1 }.sale_value(), 1);
+    }
+
+    #[test]
+    fn buying_costs_the_full_price() {
+        let (mut ecs, player, vendor) = shop(50);
+        let potion = ware(&mut ecs, vendor, Some(30));
+
+        buy(&mut ecs, vendor, potion);
+        assert_eq!(gold(&ecs, player), 20);
+        assert_eq!(owner(&ecs, potion), player);
+
+        let another = ware(&mut ecs, vendor, Some(30));
+        buy(&mut ecs, vendor, another);
+        assert_eq!(gold(&ecs, player), 20);
+        assert_eq!(owner(&ecs, another), vendor);
+    }
+
+    #[test]
+    fn selling_pays_the_sale_value() {
+        let (mut ecs, player, vendor) = shop(0);
+        let potion = ware(&mut ecs, player, Some(30));
+        let junk = ware(&mut ecs, player, None);
+
+        sell(&mut ecs, vendor, potion);
+        assert_eq!(gold(&ecs, player), 15);
+        assert_eq!(owner(&ecs, potion), vendor);
+
+        sell(&mut ecs, vendor, junk);
+        assert_eq!(gold(&ecs, player), 15);
+        assert_eq!(owner(&ecs, junk), player);
+    }
+}
@@ -6,7 +6,7 @@ use crate::{
 use rltk::{RandomNumberGenerator, RGB};
 use specs::prelude::*;
 use specs::saveload::MarkedBuilder;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 const MAX_SPAWNS: i32 = 4;
 
@@ -23,20 +23,29 @@ pub fn player(ecs: &mut World, player_x: i32, player_y: i32) -> Entity {
             render_order: 0,
         })
         .with(Player {})
+        .with(BlocksTile {})
         .with(Viewshed {
             visible_tiles: Vec::new(),
             range: 8,
             dirty: true,
+            algorithm: FovAlgorithm::Symmetric,
         })
         .with(Name {
             name: "Player".to_string(),
         })
+        .with(Description {
+            description: "A weary adventurer, determined to reach the bottom of the dungeon."
+                .to_string(),
+        })
         .with(CombatStats {
             max_hp: 30,
             hp: 30,
             defense: 2,
             power: 5,
         })
+        .with(Attributes { strength: 10 })
+        .with(Wallet { gold: 0 })
+        .with(LightSource { radius: 5 })
         .marked::<SimpleMarker<SerializeMe>>()
         .build()
 }
@@ -66,7 +75,15 @@ pub fn spawn_goodies(ecs: &mut World, room: &Rect) {
 
 pub fn spawn_room(ecs: &mut World, room: &Rect) {
     let spawn_table = room_table();
-    let mut spawn_points: HashMap<usize, String> = HashMap::new();
+    let mut spawn_points: BTreeMap<usize, String> = BTreeMap::new();
+
+    let lit = ecs
+        .write_resource::<RandomNumberGenerator>()
+        .roll_dice(1, 3)
+        > 1;
+    if lit {
+        wall_torch(ecs, (room.lft + room.rht) / 2, room.top);
+    }
 
     {
         let mut rng = ecs.write_resource::<RandomNumberGenerator>();
@@ -95,15 +112,28 @@ pub fn spawn_room(ecs: &mut World, room: &Rect) {
         match spawn.1.as_ref() {
             "Goblin" => goblin(ecs, x, y),
             "Orc" => orc(ecs, x, y),
-            "Health Potion" => health_potion(ecs, x, y),
-            "Fireball Scroll" => fireball_scroll(ecs, x, y),
-            "Confusion Scroll" => confusion_scroll(ecs, x, y),
-            "Magic Missile Scroll" => magic_missile_scroll(ecs, x, y),
-            _ => {}
+            name => {
+                spawn_item(ecs, name, x, y);
+            }
         }
     }
 }
 
+/// Creates the named item on the map, returning `None` for unknown names.
+pub fn spawn_item(ecs: &mut World, name: &str, x: i32, y: i32) -> Option<Entity> {
+    match name {
+        "Health Potion" => Some(health_potion(ecs, x, y)),
+        "Fireball Scroll" => Some(fireball_scroll(ecs, x, y)),
+        "Confusion Scroll" => Some(confusion_scroll(ecs, x, y)),
+        "Magic Missile Scroll" => Some(magic_missile_scroll(ecs, x, y)),
+        "Remove Curse Scroll" => Some(remove_curse_scroll(ecs, x, y)),
+        "Dagger" => Some(dagger(ecs, x, y)),
+        "Shield" => Some(shield(ecs, x, y)),
+        "Gold" => Some(gold(ecs, x, y)),
+        _ => None,
+    }
+}
+
 fn random_item(ecs: &mut World, x: i32, y: i32) {
     let roll = {
         let mut rng = ecs.write_resource::<RandomNumberGenerator>();
@@ -117,7 +147,40 @@ fn random_item(ecs: &mut World, x: i32, y: i32) {
     };
 }
 
-fn health_potion(ecs: &mut World, x: i32, y: i32) {
+/// Rolls how special an item turns out to be. Only equipment can be cursed,
+/// since a cursed potion or scroll would simply be used up and forgotten.
+fn roll_rarity(ecs: &mut World, can_curse: bool) -> RarityTier {
+    let roll = ecs
+        .write_resource::<RandomNumberGenerator>()
+        .roll_dice(1, 100);
+    match roll {
+        1..=5 => RarityTier::Rare,
+        6..=20 => RarityTier::Magic,
+        21..=35 if can_curse => RarityTier::Cursed,
+        _ => RarityTier::Common,
+    }
+}
+
+/// Rolls a piece of equipment's rarity. Cursed gear passes for common or
+/// magic until it is worn; everything else is what it seems.
+fn roll_equipment_rarity(ecs: &mut World) -> Rarity {
+    let tier = roll_rarity(ecs, true);
+    let disguise = if tier == RarityTier::Cursed {
+        match ecs
+            .write_resource::<RandomNumberGenerator>()
+            .roll_dice(1, 2)
+        {
+            1 => Some(RarityTier::Common),
+            _ => Some(RarityTier::Magic),
+        }
+    } else {
+        None
+    };
+    Rarity { tier, disguise }
+}
+
+fn health_potion(ecs: &mut World, x: i32, y: i32) -> Entity {
+    let tier = roll_rarity(ecs, false);
     ecs.create_entity()
         .with(Position { x, y })
         .with(Renderable {
@@ -129,14 +192,28 @@ fn health_potion(ecs: &mut World, x: i32, y: i32) {
         .with(Name {
             name: "Health Potion".to_string(),
         })
+        .with(Description {
+            description: "A small flask of red liquid. Drinking it closes wounds.".to_string(),
+        })
         .with(Item {})
         .with(Consumable {})
-        .with(ProvidesHealing { heal_amount: 8 })
+        .with(ProvidesHealing {
+            heal_amount: tier.scale(8),
+        })
+        .with(Rarity {
+            tier,
+            disguise: None,
+        })
+        .with(Weight { weight: 2 })
+        .with(Price {
+            value: tier.scale(20),
+        })
         .marked::<SimpleMarker<SerializeMe>>()
-        .build();
+        .build()
 }
 
-fn magic_missile_scroll(ecs: &mut World, x: i32, y: i32) {
+fn magic_missile_scroll(ecs: &mut World, x: i32, y: i32) -> Entity {
+    let tier = roll_rarity(ecs, false);
     ecs.create_entity()
         .with(Position { x, y })
         .with(Renderable {
@@ -148,15 +225,29 @@ fn magic_missile_scroll(ecs: &mut World, x: i32, y: i32) {
         .with(Name {
             name: "Magic Missile Scrolle".to_string(),
         })
+        .with(Description {
+            description: "A scroll that hurls a bolt of force at a single target.".to_string(),
+        })
         .with(Item {})
         .with(Consumable {})
         .with(Ranged { range: 6 })
-        .with(InflictsDamage { damage: 8 })
+        .with(Weight { weight: 1 })
+        .with(InflictsDamage {
+            damage: tier.scale(8),
+        })
+        .with(Rarity {
+            tier,
+            disguise: None,
+        })
+        .with(Price {
+            value: tier.scale(25),
+        })
         .marked::<SimpleMarker<SerializeMe>>()
-        .build();
+        .build()
 }
 
-fn fireball_scroll(ecs: &mut World, x: i32, y: i32) {
+fn fireball_scroll(ecs: &mut World, x: i32, y: i32) -> Entity {
+    let tier = roll_rarity(ecs, false);
     ecs.create_entity()
         .with(Position { x, y })
         .with(Renderable {
@@ -168,16 +259,32 @@ fn fireball_scroll(ecs: &mut World, x: i32, y: i32) {
         .with(Name {
             name: "Fireball Scroll".to_string(),
         })
+        .with(Description {
+            description:
+                "A scroll that engulfs an area in flame, burning everything caught in the blast."
+                    .to_string(),
+        })
         .with(Item {})
         .with(Consumable {})
         .with(Ranged { range: 6 })
-        .with(InflictsDamage { damage: 20 })
+        .with(Weight { weight: 1 })
+        .with(InflictsDamage {
+            damage: tier.scale(20),
+        })
+        .with(Rarity {
+            tier,
+            disguise: None,
+        })
         .with(AreaOfEffect { radius: 3 })
+        .with(Price {
+            value: tier.scale(50),
+        })
         .marked::<SimpleMarker<SerializeMe>>()
-        .build();
+        .build()
 }
 
-fn confusion_scroll(ecs: &mut World, x: i32, y: i32) {
+fn confusion_scroll(ecs: &mut World, x: i32, y: i32) -> Entity {
+    let tier = roll_rarity(ecs, false);
     ecs.create_entity()
         .with(Position { x, y })
         .with(Renderable {
@@ -189,23 +296,375 @@ fn confusion_scroll(ecs: &mut World, x: i32, y: i32) {
         .with(Name {
             name: "Confusion Scroll".to_string(),
         })
+        .with(Description {
+            description: "A scroll that addles the mind of its target for a few turns.".to_string(),
+        })
         .with(Item {})
         .with(Consumable {})
         .with(Ranged { range: 6 })
-        .with(Confusion { turns: 3 })
+        .with(Weight { weight: 1 })
+        .with(Confusion {
+            turns: tier.scale(3),
+        })
+        .with(Rarity {
+            tier,
+            disguise: None,
+        })
+        .with(Price {
+            value: tier.scale(30),
+        })
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build()
+}
+
+fn remove_curse_scroll(ecs: &mut World, x: i32, y: i32) -> Entity {
+    ecs.create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            glyph: rltk::to_cp437(')'),
+            fg: RGB::named(rltk::WHITE),
+            bg: RGB::named(rltk::BLACK),
+            render_order: 2,
+        })
+        .with(Name {
+            name: "Remove Curse Scroll".to_string(),
+        })
+        .with(Description {
+            description: "A scroll that breaks the curses on everything its reader wears."
+                .to_string(),
+        })
+        .with(Item {})
+        .with(Consumable {})
+        .with(RemovesCurse {})
+        .with(Weight { weight: 1 })
+        .with(Rarity {
+            tier: RarityTier::Common,
+            disguise: None,
+        })
+        .with(Price { value: 60 })
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build()
+}
+
+fn dagger(ecs: &mut World, x: i32, y: i32) -> Entity {
+    let rarity = roll_equipment_rarity(ecs);
+    ecs.create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            glyph: rltk::to_cp437('/'),
+            fg: RGB::named(rltk::CYAN),
+            bg: RGB::named(rltk::BLACK),
+            render_order: 2,
+        })
+        .with(Name {
+            name: "Dagger".to_string(),
+        })
+        .with(Description {
+            description: "A short, sharp blade. Better than bare fists.".to_string(),
+        })
+        .with(Item {})
+        .with(Equippable {
+            slot: EquipmentSlot::Melee,
+        })
+        .with(MeleePowerBonus { power: 2 })
+        .with(Weight { weight: 3 })
+        .with(rarity.clone())
+        .with(Price {
+            value: rarity.apparent_tier().scale(40),
+        })
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build()
+}
+
+fn shield(ecs: &mut World, x: i32, y: i32) -> Entity {
+    let rarity = roll_equipment_rarity(ecs);
+    ecs.create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            glyph: rltk::to_cp437('('),
+            fg: RGB::named(rltk::CYAN),
+            bg: RGB::named(rltk::BLACK),
+            render_order: 2,
+        })
+        .with(Name {
+            name: "Shield".to_string(),
+        })
+        .with(Description {
+            description: "A battered wooden shield, good for turning aside blows.".to_string(),
+        })
+        .with(Item {})
+        .with(Equippable {
+            slot: EquipmentSlot::Shield,
+        })
+        .with(DefenseBonus { defense: 1 })
+        .with(Weight { weight: 8 })
+        .with(rarity.clone())
+        .with(Price {
+            value: rarity.apparent_tier().scale(35),
+        })
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build()
+}
+
+fn gold(ecs: &mut World, x: i32, y: i32) -> Entity {
+    let amount = ecs
+        .write_resource::<RandomNumberGenerator>()
+        .roll_dice(3, 6);
+    ecs.create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            glyph: rltk::to_cp437('$'),
+            fg: RGB::named(rltk::GOLD),
+            bg: RGB::named(rltk::BLACK),
+            render_order: 2,
+        })
+        .with(Name {
+            name: "Gold".to_string(),
+        })
+        .with(Description {
+            description: "A scattering of coins. Shopkeepers will be glad of them.".to_string(),
+        })
+        .with(Item {})
+        .with(Gold { amount })
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build()
+}
+
+/// Lights up the room below it. Rooms without one are left dark.
+fn wall_torch(ecs: &mut World, x: i32, y: i32) {
+    ecs.create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            glyph: rltk::to_cp437('☼'),
+            fg: RGB::named(rltk::YELLOW),
+            bg: RGB::named(rltk::BLACK),
+            render_order: 3,
+        })
+        .with(Name {
+            name: "Torch".to_string(),
+        })
+        .with(Description {
+            description: "A torch in an iron bracket, burning steadily.".to_string(),
+        })
+        .with(LightSource { radius: 8 })
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build();
+}
+
+/// The remains of a dead monster, which can be eaten in a pinch.
+pub fn corpse(ecs: &mut World, x: i32, y: i32, name: &str, glyph: rltk::FontCharType) -> Entity {
+    ecs.create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            glyph,
+            fg: RGB::named(rltk::DARK_RED),
+            bg: RGB::named(rltk::BLACK),
+            render_order: 3,
+        })
+        .with(Name {
+            name: format!("{} corpse", name),
+        })
+        .with(Description {
+            description: format!(
+                "What is left of a {}. Edible, if you are not too fussy.",
+                name.to_lowercase()
+            ),
+        })
+        .with(Item {})
+        .with(Consumable {})
+        .with(ProvidesFood { nutrition: 4 })
+        .with(Weight { weight: 10 })
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build()
+}
+
+/// Sometimes places a shopkeeper with a few wares in one of the rooms, on a
+/// tile nothing else has been spawned on.
+pub fn spawn_vendor(ecs: &mut World, rooms: &[Rect]) {
+    let (x, y, stock) = {
+        let mut rng = ecs.write_resource::<RandomNumberGenerator>();
+        if rooms.len() < 2 || rng.roll_dice(1, 3) > 1 {
+            return;
+        }
+        let room = &rooms[rng.range(1, rooms.len())];
+        let positions = ecs.read_storage::<Position>();
+        let free = (0..20)
+            .map(|_| {
+                (
+                    room.lft + rng.roll_dice(1, room.rht - room.lft),
+                    room.top + rng.roll_dice(1, room.bot - room.top),
+                )
+            })
+            .find(|(x, y)| !positions.join().any(|pos| pos.x == *x && pos.y == *y));
+        let (x, y) = match free {
+            Some(tile) => tile,
+            None => return,
+        };
+        let table = shop_table();
+        let stock: Vec<String> = (0..rng.roll_dice(1, 4) + 3)
+            .map(|_| table.roll(&mut rng))
+            .collect();
+        (x, y, stock)
+    };
+
+    let vendor = ecs
+        .create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            glyph: rltk::to_cp437('@'),
+            fg: RGB::named(rltk::GOLD),
+            bg: RGB::named(rltk::BLACK),
+            render_order: 1,
+        })
+        .with(Name {
+            name: "Shopkeeper".to_string(),
+        })
+        .with(Description {
+            description:
+                "A trader who somehow makes a living this far down. Bump into them to trade."
+                    .to_string(),
+        })
+        .with(Vendor {})
+        .with(BlocksTile {})
         .marked::<SimpleMarker<SerializeMe>>()
         .build();
+
+    for (order, name) in stock.iter().enumerate() {
+        if let Some(item) = spawn_item(ecs, name, x, y) {
+            ecs.write_storage::<Position>().remove(item);
+            ecs.write_storage::<InBackpack>()
+                .insert(
+                    item,
+                    InBackpack {
+                        owner: vendor,
+                        order: order as i32,
+                    },
+                )
+                .expect("Unable to stock vendor");
+        }
+    }
 }
 
 fn orc(ecs: &mut World, x: i32, y: i32) {
-    monster(ecs, x, y, rltk::to_cp437('o'), "Orc")
+    let orc = monster(
+        ecs,
+        x,
+        y,
+        rltk::to_cp437('o'),
+        "Orc",
+        "A hulking brute with a notched axe, a short temper and a sputtering torch.",
+    );
+    ecs.write_storage::<LightSource>()
+        .insert(orc, LightSource { radius: 3 })
+        .expect("Unable to insert light source");
 }
 
-fn goblin(ecs: &mut World, x: i32, y: i32) {
-    monster(ecs, x, y, rltk::to_cp437('g'), "Goblin")
+pub fn goblin(ecs: &mut World, x: i32, y: i32) {
+    let goblin = monster(
+        ecs,
+        x,
+        y,
+        rltk::to_cp437('g'),
+        "Goblin",
+        "A small, wiry creature that makes up in numbers what it lacks in size. It will grab anything shiny.",
+    );
+    ecs.write_storage::<UsesItems>()
+        .insert(goblin, UsesItems {})
+        .expect("Unable to insert item user");
 }
 
-fn monster<S: ToString>(ecs: &mut World, x: i32, y: i32, glyph: rltk::FontCharType, name: S) {
+/// Puts the boss in the middle of the final level's last room, carrying
+/// the item the player came down here for.
+pub fn spawn_boss(ecs: &mut World, room: &Rect) {
+    let (x, y) = room.center();
+    let boss = monster(
+        ecs,
+        x,
+        y,
+        rltk::to_cp437('O'),
+        "Orc Warlord",
+        "A scarred giant of an orc in a crown of black iron. Its wounds close as you watch, and goblins come running when it roars.",
+    );
+    ecs.write_storage::<CombatStats>()
+        .insert(
+            boss,
+            CombatStats {
+                max_hp: 60,
+                hp: 60,
+                defense: 3,
+                power: 8,
+            },
+        )
+        .expect("Unable to insert combat stats");
+    ecs.write_storage::<Renderable>()
+        .insert(
+            boss,
+            Renderable {
+                glyph: rltk::to_cp437('O'),
+                fg: RGB::named(rltk::MAGENTA),
+                bg: RGB::named(rltk::BLACK),
+                render_order: 1,
+            },
+        )
+        .expect("Unable to insert renderable");
+    ecs.write_storage::<Boss>()
+        .insert(
+            boss,
+            Boss {
+                summon_cooldown: crate::boss::SUMMON_COOLDOWN,
+            },
+        )
+        .expect("Unable to insert boss");
+    ecs.write_storage::<LightSource>()
+        .insert(boss, LightSource { radius: 4 })
+        .expect("Unable to insert light source");
+
+    let goal = iron_crown(ecs, x, y);
+    ecs.write_storage::<Position>().remove(goal);
+    ecs.write_storage::<InBackpack>()
+        .insert(
+            goal,
+            InBackpack {
+                owner: boss,
+                order: 0,
+            },
+        )
+        .expect("Unable to give the boss its crown");
+}
+
+/// The goal of the game. Whoever walks out with it has won.
+fn iron_crown(ecs: &mut World, x: i32, y: i32) -> Entity {
+    ecs.create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            glyph: rltk::to_cp437('Ω'),
+            fg: RGB::named(rltk::MAGENTA),
+            bg: RGB::named(rltk::BLACK),
+            render_order: 2,
+        })
+        .with(Name {
+            name: "Iron Crown".to_string(),
+        })
+        .with(Description {
+            description:
+                "The crown of the orc warlords, heavy and cold. Claim it and the dungeon is yours."
+                    .to_string(),
+        })
+        .with(Item {})
+        .with(GoalItem {})
+        .with(Weight { weight: 5 })
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build()
+}
+
+fn monster<S: ToString>(
+    ecs: &mut World,
+    x: i32,
+    y: i32,
+    glyph: rltk::FontCharType,
+    name: S,
+    description: S,
+) -> Entity {
     ecs.create_entity()
         .with(Position { x, y })
         .with(Renderable {
@@ -218,12 +677,19 @@ fn monster<S: ToString>(ecs: &mut World, x: i32, y: i32, glyph: rltk::FontCharTy
             visible_tiles: Vec::new(),
             range: 8,
             dirty: true,
+            algorithm: FovAlgorithm::Symmetric,
         })
         .with(Monster {})
         .with(Name {
             name: name.to_string(),
         })
+        .with(Description {
+            description: description.to_string(),
+        })
         .with(BlocksTile {})
+        .with(LootTable {
+            table: name.to_string(),
+        })
         .with(CombatStats {
             max_hp: 16,
             hp: 16,
@@ -231,7 +697,7 @@ fn monster<S: ToString>(ecs: &mut World, x: i32, y: i32, glyph: rltk::FontCharTy
             power: 4,
         })
         .marked::<SimpleMarker<SerializeMe>>()
-        .build();
+        .build()
 }
 
 fn room_table() -> RandomTable {
@@ -242,4 +708,38 @@ fn room_table() -> RandomTable {
         .add("Fireball Scroll", 2)
         .add("Confusion Scroll", 2)
         .add("Magic Missile Scroll", 4)
+        .add("Remove Curse Scroll", 2)
+        .add("Dagger", 3)
+        .add("Shield", 3)
+        .add("Gold", 5)
+}
+
+/// What a monster might leave behind when it dies. Unknown entries, such as
+/// "Nothing", spawn no item.
+pub fn loot_table(table: &str) -> RandomTable {
+    match table {
+        "Goblin" => RandomTable::new()
+            .add("Nothing", 10)
+            .add("Gold", 6)
+            .add("Health Potion", 2)
+            .add("Dagger", 1),
+        "Orc" => RandomTable::new()
+            .add("Nothing", 4)
+            .add("Gold", 8)
+            .add("Health Potion", 3)
+            .add("Shield", 2)
+            .add("Fireball Scroll", 1),
+        _ => RandomTable::new(),
+    }
+}
+
+fn shop_table() -> RandomTable {
+    RandomTable::new()
+        .add("Health Potion", 5)
+        .add("Magic Missile Scroll", 3)
+        .add("Fireball Scroll", 1)
+        .add("Confusion Scroll", 2)
+        .add("Remove Curse Scroll", 2)
+        .add("Dagger", 2)
+        .add("Shield", 2)
 }
@@ -1,7 +1,9 @@
-use super::map::Map;
-use super::{Player, Position, Viewshed};
-use rltk::{field_of_view, Point};
+use super::fov::field_of_view;
+use super::map::{Map, RememberedEntity};
+use super::{Player, Position, Renderable, Viewshed};
+use rltk::Point;
 use specs::prelude::*;
+use std::collections::HashMap;
 
 pub struct VisibilitySystem {}
 
@@ -12,21 +14,32 @@ impl<'a> System<'a> for VisibilitySystem {
         WriteStorage<'a, Viewshed>,
         WriteStorage<'a, Position>,
         ReadStorage<'a, Player>,
+        ReadStorage<'a, Renderable>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (mut map, entities, mut viewshed, pos, player) = data;
+        let (mut map, entities, mut viewshed, pos, player, renderables) = data;
+        let mut player_sight_changed = false;
         for (ent, viewshed, pos) in (&entities, &mut viewshed, &pos).join() {
             if viewshed.dirty {
                 viewshed.dirty = false;
                 viewshed.visible_tiles.clear();
-                viewshed.visible_tiles =
-                    field_of_view(Point::new(pos.x, pos.y), viewshed.range, &*map);
+                viewshed.visible_tiles = field_of_view(
+                    viewshed.algorithm,
+                    Point::new(pos.x, pos.y),
+                    viewshed.range,
+                    &map,
+                );
                 viewshed
                     .visible_tiles
                     .retain(|p| p.x >= 0 && p.x < map.width && p.y >= 0 && p.y < map.height);
 
+                // The player needs light to see by; monsters make do in the
+                // dark.
                 if player.get(ent).is_some() {
+                    viewshed
+                        .visible_tiles
+                        .retain(|p| map.light[map.xy_idx(p.x, p.y)] > 0.0);
                     for t in map.visible_tiles.iter_mut() {
                         *t = false;
                     }
@@ -35,8 +48,52 @@ impl<'a> System<'a> for VisibilitySystem {
                         map.revealed_tiles[idx] = true;
                         map.visible_tiles[idx] = true;
                     }
+                    player_sight_changed = true;
                 }
             }
         }
+
+        if player_sight_changed {
+            remember_visible_entities(&mut map, &entities, &pos, &player, &renderables);
+        }
+    }
+}
+
+/// Replaces the memory of every tile in sight with the topmost thing now on
+/// it, so monsters and items stay marked where they were last seen.
+fn remember_visible_entities(
+    map: &mut Map,
+    entities: &Entities,
+    positions: &WriteStorage<Position>,
+    player: &ReadStorage<Player>,
+    renderables: &ReadStorage<Renderable>,
+) {
+    let mut topmost: HashMap<usize, &Renderable> = HashMap::new();
+    for (entity, pos, render) in (entities, positions, renderables).join() {
+        if player.get(entity).is_some() {
+            continue;
+        }
+        let idx = map.xy_idx(pos.x, pos.y);
+        if !map.visible_tiles[idx] {
+            continue;
+        }
+        if topmost
+            .get(&idx)
+            .is_none_or(|shown| render.render_order < shown.render_order)
+        {
+            topmost.insert(idx, render);
+        }
+    }
+
+    let visible_tiles = &map.visible_tiles;
+    map.remembered.retain(|idx, _| !visible_tiles[*idx]);
+    for (idx, render) in topmost {
+        map.remembered.insert(
+            idx,
+            RememberedEntity {
+                glyph: render.glyph,
+                fg: render.fg,
+            },
+        );
     }
 }
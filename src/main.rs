@@ -1,7 +1,5 @@
-use rltk::Point;
 use specs::prelude::*;
 
-use blast::map::Map;
 use blast::*;
 
 fn main() -> rltk::BError {
@@ -10,53 +8,34 @@ fn main() -> rltk::BError {
         .with_title("Roguelike Tutorial")
         .build()?;
     let mut gs = State { ecs: World::new() };
-    gs.ecs.register::<Position>();
-    gs.ecs.register::<Renderable>();
-    gs.ecs.register::<Player>();
-    gs.ecs.register::<Viewshed>();
-    gs.ecs.register::<Monster>();
-    gs.ecs.register::<Name>();
-    gs.ecs.register::<BlocksTile>();
-    gs.ecs.register::<CombatStats>();
-    gs.ecs.register::<WantsToMelee>();
-    gs.ecs.register::<SufferDamage>();
-    gs.ecs.register::<Item>();
-    gs.ecs.register::<ProvidesHealing>();
-    gs.ecs.register::<WantsToPickupItem>();
-    gs.ecs.register::<WantsToDropItem>();
-    gs.ecs.register::<InBackpack>();
-    gs.ecs.register::<WantsToUseItem>();
-    gs.ecs.register::<Consumable>();
-    gs.ecs.register::<Ranged>();
-    gs.ecs.register::<InflictsDamage>();
-    gs.ecs.register::<AreaOfEffect>();
-    gs.ecs.register::<Confusion>();
-    gs.ecs.register::<SimpleMarker<SerializeMe>>();
-    gs.ecs.register::<SerializationHelper>();
-
-    gs.ecs.insert(SimpleMarkerAllocator::<SerializeMe>::new());
-    gs.ecs.insert(rltk::RandomNumberGenerator::new());
-    gs.ecs.insert(RunState::MainMenu {
-        menu_selection: gui::MainMenuSelection::NewGame,
-    });
-    gs.ecs.insert(gamelog::GameLog {
-        entries: vec!["Welcome to Rusty Roguelike".to_string()],
-    });
-
-    let (map, room) = Map::new_map(1);
-    let (px, py) = room.center();
-
-    for room in map.rooms.iter().skip(1) {
-        spawner::spawn_room(&mut gs.ecs, room);
-    }
-    spawner::spawn_goodies(&mut gs.ecs, map.rooms.get(0).unwrap());
-
-    gs.ecs.insert(map);
-
-    let player_entity = spawner::player(&mut gs.ecs, px, py);
-    gs.ecs.insert(player_entity);
-
-    gs.ecs.insert(Point::new(px, py));
+    let replay = match std::env::args().nth(1).as_deref() {
+        Some("--replay") => {
+            let path = std::env::args()
+                .nth(2)
+                .expect("Usage: hello --replay <file>");
+            match replay::load_replay(&path) {
+                Ok(replay) => replay,
+                Err(e) => {
+                    rltk::console::log(format!("Not replaying {}: {}", path, e));
+                    replay::Replay::new(rltk::RandomNumberGenerator::new().next_u64())
+                }
+            }
+        }
+        _ => replay::Replay::new(rltk::RandomNumberGenerator::new().next_u64()),
+    };
+    let runstate = if replay.playback.is_empty() {
+        RunState::MainMenu {
+            menu_selection: gui::MainMenuSelection::NewGame,
+        }
+    } else {
+        RunState::PreRun
+    };
+    gs.ecs.insert(replay);
+    gs.ecs.insert(runstate);
+    gs.ecs.insert(saveload_system::AutosaveSettings::load());
+    gs.ecs.insert(keymap::Keymap::load());
+    gs.ecs.insert(score::HighScores::load());
+    gs.new_game();
 
     rltk::main_loop(context, gs)
 }